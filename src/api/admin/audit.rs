@@ -0,0 +1,110 @@
+use crate::api::admin::is_admin;
+use crate::api::{to_json, validate, JsonValue};
+use crate::auth::Token;
+use crate::db::audit::{search as db_search, AuditFilter, AuditLog};
+use crate::error::SUCCESS_CODE;
+use crate::types::Email;
+use chrono::NaiveDateTime;
+use poem::web::{Data as PoemData, Json};
+use poem::{handler, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SearchAuditReq {
+    #[validate]
+    actor: Option<Email>,
+    //如 `book.update`，见 audit::Action
+    #[validate(length(max = 64))]
+    action: Option<String>,
+    //模糊匹配 ISBN 或邮箱
+    #[validate(length(max = 255))]
+    target: Option<String>,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+    #[validate(range(min = 1))]
+    page: Option<u64>,
+    #[validate(range(min = 1, max = 100))]
+    page_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchAuditResp {
+    code: u32,
+    data: SearchData,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchData {
+    items: Vec<Item>,
+    total: u64,
+    page: u64,
+    page_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Item {
+    id: Option<i64>,
+    actor: Email,
+    action: String,
+    target: String,
+    diff: Option<Value>,
+    ip: Option<String>,
+    created_date: NaiveDateTime,
+    failure_of: Option<i64>,
+    error: Option<String>,
+}
+
+impl From<AuditLog> for Item {
+    fn from(log: AuditLog) -> Self {
+        Self {
+            id: log.id,
+            actor: log.actor,
+            action: log.action,
+            target: log.target,
+            diff: log.diff.and_then(|diff| serde_json::from_str(&diff).ok()),
+            ip: log.ip,
+            created_date: log.created_date,
+            failure_of: log.failure_of,
+            error: log.error,
+        }
+    }
+}
+
+#[handler]
+pub async fn search(
+    Json(req): Json<SearchAuditReq>,
+    PoemData(token): PoemData<&Token>,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+    validate(&req)?;
+
+    let filter = AuditFilter {
+        actor: req.actor,
+        action: req.action.filter(|s| !s.is_empty()),
+        target: req.target.filter(|s| !s.is_empty()),
+        from: req.from,
+        to: req.to,
+    };
+    let page = req.page.unwrap_or(1);
+    let page_size = req
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE);
+
+    let result = db_search(&filter, page, page_size).await?;
+
+    Ok(to_json(SearchAuditResp {
+        code: SUCCESS_CODE,
+        data: SearchData {
+            items: result.records.into_iter().map(Item::from).collect(),
+            total: result.total,
+            page,
+            page_size,
+        },
+    }))
+}
@@ -1,13 +1,56 @@
-use crate::api::admin::is_admin;
-use crate::api::{new_success_resp, validate, JsonValue};
+use crate::api::admin::{is_admin, is_staff};
+use crate::api::{new_image_resp, new_success_resp, to_json, validate, JsonValue};
+use crate::audit::{diff, snapshot, Action, Audit};
 use crate::auth::Token;
-use crate::db::book::{add, delete as db_delete, update as db_update, Book, UpdateBook};
-use crate::types::{Author, Bookname, Isbn, Press, Stock};
-use poem::web::{Data, Json};
-use poem::{handler, Result};
-use serde::Deserialize;
+use crate::bulk::book::{export, import, ImportOptions, ImportReport};
+use crate::bulk::Format;
+use crate::db::book::{
+    add, list_contributors, list_subjects, query_by_isbn, restore as db_restore,
+    update as db_update, withdraw, Book, Contributor, UpdateBook,
+};
+use crate::error::{Error, SUCCESS_CODE};
+use crate::metadata::{lookup as lookup_metadata, BookMetadata};
+use crate::types::{
+    isbn_hyphenated, Author, BookStatus, Bookname, CallNumber, ContributorRole, Description,
+    Edition, Isbn, Language, PageCount, Press, PublicationYear, ShelfLocation, Stock, Subject,
+    WithdrawnReason,
+};
+use crate::upload::{read_image, save_image};
+use poem::http::header;
+use poem::web::{Data, Json, Multipart, Query, RemoteAddr};
+use poem::{handler, IntoResponse, Response, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use validator::Validate;
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct ContributorReq {
+    #[validate]
+    name: Author,
+    role: ContributorRole,
+}
+
+fn to_contributors(isbn: &Isbn, contributors: Vec<ContributorReq>) -> Vec<Contributor> {
+    contributors
+        .into_iter()
+        .enumerate()
+        .map(|(i, c)| Contributor {
+            isbn: isbn.clone(),
+            name: c.name,
+            role: c.role,
+            position: i as u32,
+        })
+        .collect()
+}
+
+fn join_isbns(isbns: &[Isbn]) -> String {
+    isbns
+        .iter()
+        .map(Isbn::as_str)
+        .collect::<Vec<&str>>()
+        .join(",")
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct AddBookReq {
     #[validate]
@@ -20,43 +63,131 @@ pub struct AddBookReq {
     press: Press,
     #[validate]
     stock: Stock,
+    #[validate]
+    publication_year: Option<PublicationYear>,
+    #[validate]
+    edition: Option<Edition>,
+    #[validate]
+    language: Option<Language>,
+    #[validate]
+    page_count: Option<PageCount>,
+    #[validate]
+    description: Option<Description>,
+    #[validate]
+    call_number: Option<CallNumber>,
+    #[validate]
+    shelf_location: Option<ShelfLocation>,
+    #[validate]
+    #[serde(default)]
+    contributors: Vec<ContributorReq>,
+    #[validate]
+    #[serde(default)]
+    subjects: Vec<Subject>,
 }
 
 #[handler]
-pub async fn add_book(Json(req): Json<AddBookReq>, Data(token): Data<&Token>) -> Result<JsonValue> {
+pub async fn add_book(
+    Json(req): Json<AddBookReq>,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
     is_admin(token)?;
     validate(&req)?;
 
+    let contributors = to_contributors(&req.isbn, req.contributors);
     let book = Book {
         name: req.name,
         author: req.author,
-        isbn: req.isbn,
+        isbn: req.isbn.clone(),
         press: req.press,
         stock: req.stock,
         remain: req.stock,
+        publication_year: req.publication_year,
+        edition: req.edition,
+        language: req.language,
+        page_count: req.page_count,
+        description: req.description,
+        call_number: req.call_number,
+        shelf_location: req.shelf_location,
+        cover: None,
+        status: BookStatus::Available,
+        withdrawn_date: None,
+        withdrawn_reason: None,
     };
 
-    add(book).await?;
+    let mut after = snapshot(&book);
+    after["contributors"] = snapshot(&contributors);
+    after["subjects"] = snapshot(&req.subjects);
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::BookAdd,
+            req.isbn.as_str(),
+            diff(&Value::Null, &after),
+            async |tx| add(tx, book, &contributors, &req.subjects).await,
+        )
+        .await?;
 
-    Ok(Json(serde_json::json! ({
-        "code": 20000,
-    })))
+    Ok(new_success_resp())
 }
 
 #[derive(Debug, Deserialize, Validate)]
 pub struct DeleteReq {
     #[validate]
     isbns: Vec<Isbn>,
+    #[validate]
+    reason: Option<WithdrawnReason>,
+}
+
+//删除即下架，借阅历史保留，可通过 restore 恢复
+#[handler]
+pub async fn delete(
+    Json(req): Json<DeleteReq>,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    validate(&req)?;
+    is_admin(token)?;
+
+    let after = json!({ "status": BookStatus::Withdrawn, "withdrawn_reason": req.reason });
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::BookWithdraw,
+            &join_isbns(&req.isbns),
+            diff(&json!({ "status": BookStatus::Available }), &after),
+            async |tx| withdraw(tx, &req.isbns, &req.reason).await,
+        )
+        .await?;
+
+    Ok(new_success_resp())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RestoreReq {
+    #[validate]
+    isbns: Vec<Isbn>,
 }
 
 #[handler]
-pub async fn delete(Json(req): Json<DeleteReq>, Data(token): Data<&Token>) -> Result<JsonValue> {
+pub async fn restore(
+    Json(req): Json<RestoreReq>,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
     validate(&req)?;
     is_admin(token)?;
 
-    for isbn in &req.isbns {
-        db_delete(isbn).await?;
-    }
+    let after = json!({ "status": BookStatus::Available });
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::BookRestore,
+            &join_isbns(&req.isbns),
+            diff(&json!({ "status": BookStatus::Withdrawn }), &after),
+            async |tx| db_restore(tx, &req.isbns).await,
+        )
+        .await?;
 
     Ok(new_success_resp())
 }
@@ -73,24 +204,227 @@ pub struct UpdateBookReq {
     press: Option<Press>,
     #[validate]
     stock: Option<Stock>,
+    #[validate]
+    publication_year: Option<PublicationYear>,
+    #[validate]
+    edition: Option<Edition>,
+    #[validate]
+    language: Option<Language>,
+    #[validate]
+    page_count: Option<PageCount>,
+    #[validate]
+    description: Option<Description>,
+    #[validate]
+    call_number: Option<CallNumber>,
+    #[validate]
+    shelf_location: Option<ShelfLocation>,
+    //提供时整体替换原有的责任者或主题分类
+    #[validate]
+    contributors: Option<Vec<ContributorReq>>,
+    #[validate]
+    subjects: Option<Vec<Subject>>,
 }
 
 #[handler]
 pub async fn update(
     Json(req): Json<UpdateBookReq>,
     Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
 ) -> Result<JsonValue> {
     validate(&req)?;
     is_admin(token)?;
+    let old = query_by_isbn(&req.isbn).await.ok_or(Error::BookNotExist)?;
     let book = UpdateBook {
         name: req.name,
         author: req.author,
         press: req.press,
         stock: req.stock,
         remain: None,
+        publication_year: req.publication_year,
+        edition: req.edition,
+        language: req.language,
+        page_count: req.page_count,
+        description: req.description,
+        call_number: req.call_number,
+        shelf_location: req.shelf_location,
+        cover: None,
     };
 
-    db_update(&req.isbn, book).await?;
+    let contributors = req
+        .contributors
+        .map(|contributors| to_contributors(&req.isbn, contributors));
+
+    let isbns = [req.isbn.clone()];
+    let mut before = snapshot(&old);
+    before["contributors"] = snapshot(&list_contributors(&isbns).await?);
+    before["subjects"] = snapshot(
+        &list_subjects(&isbns)
+            .await?
+            .into_iter()
+            .map(|s| s.subject)
+            .collect::<Vec<Subject>>(),
+    );
+    let mut after = snapshot(&book);
+    after["contributors"] = snapshot(&contributors);
+    after["subjects"] = snapshot(&req.subjects);
+
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::BookUpdate,
+            req.isbn.as_str(),
+            diff(&before, &after),
+            async |tx| {
+                let (contributors, subjects) = (contributors.as_deref(), req.subjects.as_deref());
+                db_update(tx, &req.isbn, book, contributors, subjects).await
+            },
+        )
+        .await?;
 
     Ok(new_success_resp())
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ImportReq {
+    format: Format,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    upsert: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportResp {
+    code: u32,
+    data: ImportReport,
+}
+
+#[handler]
+pub async fn import_books(
+    Query(req): Query<ImportReq>,
+    body: Vec<u8>,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+
+    let opts = ImportOptions {
+        dry_run: req.dry_run,
+        upsert: req.upsert,
+    };
+    //试运行不修改数据，不需要审计
+    let report = if req.dry_run {
+        import(&body, req.format, opts, None).await?
+    } else {
+        //整个文件和其中的每一行各记录一条审计日志
+        let audit = Audit::new(&token.email, remote_addr);
+        audit
+            .record_batch(
+                Action::BookImport,
+                &format!("{} bytes of {}", body.len(), req.format.extension()),
+                None,
+                import(&body, req.format, opts, Some(&audit)),
+            )
+            .await?
+    };
+
+    Ok(to_json(ImportResp {
+        code: SUCCESS_CODE,
+        data: report,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportReq {
+    format: Format,
+}
+
+#[handler]
+pub async fn export_books(
+    Query(req): Query<ExportReq>,
+    Data(token): Data<&Token>,
+) -> Result<Response> {
+    is_admin(token)?;
+
+    let data = export(req.format).await?;
+    let filename = format!("attachment; filename=\"books.{}\"", req.format.extension());
+
+    Ok(data
+        .with_content_type(req.format.content_type())
+        .with_header(header::CONTENT_DISPOSITION, filename)
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CoverReq {
+    #[validate]
+    isbn: Isbn,
+}
+
+#[handler]
+pub async fn upload_cover(
+    Query(req): Query<CoverReq>,
+    multipart: Multipart,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+    validate(&req)?;
+    let old = query_by_isbn(&req.isbn).await.ok_or(Error::BookNotExist)?;
+
+    let name = save_image(read_image(multipart).await?).await?;
+    let book = UpdateBook {
+        cover: Some(name.clone()),
+        ..Default::default()
+    };
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::BookCover,
+            req.isbn.as_str(),
+            diff(&old, &book),
+            async |tx| db_update(tx, &req.isbn, book, None, None).await,
+        )
+        .await?;
+
+    Ok(new_image_resp(&name))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct LookupReq {
+    #[validate]
+    isbn: Isbn,
+}
+
+#[derive(Debug, Serialize)]
+struct LookupResp {
+    code: u32,
+    data: LookupData,
+}
+
+//可直接作为 AddBookReq 的初始值，stock 需由管理员填写
+#[derive(Debug, Serialize)]
+struct LookupData {
+    #[serde(serialize_with = "isbn_hyphenated")]
+    isbn: Isbn,
+    #[serde(flatten)]
+    book: BookMetadata,
+    cached: bool,
+}
+
+#[handler]
+pub async fn lookup(Query(req): Query<LookupReq>, Data(token): Data<&Token>) -> Result<JsonValue> {
+    is_staff(token)?;
+    validate(&req)?;
+
+    let result = lookup_metadata(&req.isbn).await?;
+
+    Ok(to_json(LookupResp {
+        code: SUCCESS_CODE,
+        data: LookupData {
+            isbn: req.isbn,
+            book: result.metadata,
+            cached: result.cached,
+        },
+    }))
+}
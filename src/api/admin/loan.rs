@@ -0,0 +1,350 @@
+use crate::api::admin::{is_admin, is_staff};
+use crate::api::{new_success_resp, to_json, validate, JsonValue};
+use crate::audit::{diff, Action, Audit};
+use crate::auth::Token;
+use crate::db::record::{
+    borrow, due_date, is_overdue, list_holders, list_loans, loan_summary,
+    return_book as db_return_book, BorrowedBook, LoanFilter,
+};
+use crate::db::user::{query, query_batch, query_by_sid, User};
+use crate::error::{Error, SUCCESS_CODE};
+use crate::i18n;
+use crate::types::{isbn_hyphenated, Bookname, Email, Isbn, Sid, Status, Username};
+use chrono::NaiveDateTime;
+use poem::web::{Data as PoemData, Json, RemoteAddr};
+use poem::{handler, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use validator::Validate;
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Debug, Serialize)]
+struct Item {
+    name: Bookname,
+    #[serde(serialize_with = "isbn_hyphenated")]
+    isbn: Isbn,
+    email: Email,
+    username: Option<Username>,
+    sid: Option<Sid>,
+    borrowed_date: NaiveDateTime,
+    due_date: NaiveDateTime,
+    overdue: bool,
+}
+
+fn to_items(loans: Vec<BorrowedBook>, users: Vec<User>) -> Vec<Item> {
+    let users: HashMap<Email, User> = users
+        .into_iter()
+        .map(|user| (user.email.clone(), user))
+        .collect();
+
+    loans
+        .into_iter()
+        .map(|loan| {
+            let user = users.get(&loan.email);
+            Item {
+                name: loan.book_name,
+                isbn: loan.isbn,
+                username: user.map(|u| u.username.clone()),
+                sid: user.map(|u| u.sid.clone()),
+                email: loan.email,
+                due_date: due_date(&loan.borrowed_date),
+                overdue: is_overdue(&loan.borrowed_date),
+                borrowed_date: loan.borrowed_date,
+            }
+        })
+        .collect()
+}
+
+async fn with_users(loans: Vec<BorrowedBook>) -> Result<Vec<Item>, Error> {
+    let mut emails: Vec<Email> = loans.iter().map(|loan| loan.email.clone()).collect();
+    emails.sort();
+    emails.dedup();
+    let users = query_batch(&emails).await?;
+    Ok(to_items(loans, users))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ListLoanReq {
+    #[validate]
+    isbn: Option<Isbn>,
+    #[validate]
+    email: Option<Email>,
+    overdue: Option<bool>,
+    #[validate(range(min = 1))]
+    page: Option<u64>,
+    #[validate(range(min = 1, max = 100))]
+    page_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ListLoanResp {
+    code: u32,
+    data: ListLoanData,
+}
+
+#[derive(Debug, Serialize)]
+struct ListLoanData {
+    items: Vec<Item>,
+    total: u64,
+    page: u64,
+    page_size: u64,
+}
+
+#[handler]
+pub async fn list(
+    Json(req): Json<ListLoanReq>,
+    PoemData(token): PoemData<&Token>,
+) -> Result<JsonValue> {
+    is_staff(token)?;
+    validate(&req)?;
+
+    let filter = LoanFilter {
+        isbn: req.isbn,
+        email: req.email,
+        overdue: req.overdue,
+    };
+    let page = req.page.unwrap_or(1);
+    let page_size = req
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE);
+
+    let result = list_loans(&filter, page, page_size).await?;
+    let items = with_users(result.records).await?;
+
+    Ok(to_json(ListLoanResp {
+        code: SUCCESS_CODE,
+        data: ListLoanData {
+            items,
+            total: result.total,
+            page,
+            page_size,
+        },
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct HoldersReq {
+    #[validate]
+    isbn: Isbn,
+}
+
+#[derive(Debug, Serialize)]
+struct HoldersResp {
+    code: u32,
+    data: HoldersData,
+}
+
+#[derive(Debug, Serialize)]
+struct HoldersData {
+    items: Vec<Item>,
+}
+
+#[handler]
+pub async fn holders(
+    Json(req): Json<HoldersReq>,
+    PoemData(token): PoemData<&Token>,
+) -> Result<JsonValue> {
+    is_staff(token)?;
+    validate(&req)?;
+
+    let loans = list_holders(&req.isbn).await?;
+    let items = with_users(loans).await?;
+
+    Ok(to_json(HoldersResp {
+        code: SUCCESS_CODE,
+        data: HoldersData { items },
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserSummaryReq {
+    #[validate]
+    email: Email,
+    #[validate(range(min = 1))]
+    page: Option<u64>,
+    #[validate(range(min = 1, max = 100))]
+    page_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct UserSummaryResp {
+    code: u32,
+    data: UserSummaryData,
+}
+
+#[derive(Debug, Serialize)]
+struct UserSummaryData {
+    email: Email,
+    username: Username,
+    sid: Sid,
+    active: u64,
+    overdue: u64,
+    returned: u64,
+    items: Vec<Item>,
+    total: u64,
+    page: u64,
+    page_size: u64,
+}
+
+#[handler]
+pub async fn user_summary(
+    Json(req): Json<UserSummaryReq>,
+    PoemData(token): PoemData<&Token>,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+    validate(&req)?;
+
+    let user = query(&req.email).await.ok_or(Error::UserNotExist)?;
+    let summary = loan_summary(&req.email).await?;
+    let filter = LoanFilter {
+        email: Some(req.email),
+        ..Default::default()
+    };
+    let page = req.page.unwrap_or(1);
+    let page_size = req
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE);
+    let loans = list_loans(&filter, page, page_size).await?;
+    let items = to_items(loans.records, vec![user.clone()]);
+
+    Ok(to_json(UserSummaryResp {
+        code: SUCCESS_CODE,
+        data: UserSummaryData {
+            email: user.email,
+            username: user.username,
+            sid: user.sid,
+            active: summary.active,
+            overdue: summary.overdue,
+            returned: summary.returned,
+            items,
+            total: loans.total,
+            page,
+            page_size,
+        },
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DeskReq {
+    #[validate]
+    email: Option<Email>,
+    #[validate]
+    sid: Option<Sid>,
+    #[validate]
+    isbns: Vec<Isbn>,
+}
+
+//柜台办理时通过邮箱或学号确定读者
+async fn find_patron(email: &Option<Email>, sid: &Option<Sid>) -> Result<User, Error> {
+    let user = match (email, sid) {
+        (Some(email), _) => query(email).await.ok_or(Error::UserNotExist)?,
+        (None, Some(sid)) => {
+            let mut users = query_by_sid(sid).await?;
+            match users.len() {
+                0 => return Err(Error::UserNotExist),
+                1 => users.remove(0),
+                _ => return Err(Error::AmbiguousSid),
+            }
+        }
+        (None, None) => {
+            return Err(Error::InvalidData(i18n::message(
+                "detail.email_or_sid_required",
+            )));
+        }
+    };
+
+    Ok(user)
+}
+
+#[handler]
+pub async fn checkout(
+    Json(req): Json<DeskReq>,
+    PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    is_staff(token)?;
+    validate(&req)?;
+
+    let patron = find_patron(&req.email, &req.sid).await?;
+    patron
+        .status
+        .ne(&Status::Disabled)
+        .then_some(())
+        .ok_or(Error::AccountWasDisabled)?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::LoanCheckout,
+            patron.email.as_str(),
+            diff(&Value::Null, &json!({ "isbns": req.isbns })),
+            async |tx| borrow(tx, &patron.email, &req.isbns, Some(&token.email)).await,
+        )
+        .await?;
+
+    Ok(new_success_resp())
+}
+
+#[handler]
+pub async fn checkin(
+    Json(req): Json<DeskReq>,
+    PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    is_staff(token)?;
+    validate(&req)?;
+
+    let patron = find_patron(&req.email, &req.sid).await?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::LoanCheckin,
+            patron.email.as_str(),
+            diff(&Value::Null, &json!({ "isbns": req.isbns })),
+            async |tx| db_return_book(tx, &patron.email, &req.isbns, Some(&token.email)).await,
+        )
+        .await?;
+
+    Ok(new_success_resp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{insert_user, run};
+    use crate::types::Role;
+
+    #[test]
+    fn find_patron_by_email_or_sid() {
+        run(async {
+            insert_user("one@patron.test", "202300000301", Role::User).await;
+            insert_user("two@patron.test", "202300000302", Role::User).await;
+            insert_user("twin@patron.test", "202300000302", Role::User).await;
+
+            let email = Some(Email::from("one@patron.test"));
+            let user = find_patron(&email, &None).await.unwrap();
+            assert_eq!(user.email.as_str(), "one@patron.test");
+            //同时提供时以邮箱为准
+            let sid = Some(Sid::from("202300000302"));
+            let user = find_patron(&email, &sid).await.unwrap();
+            assert_eq!(user.email.as_str(), "one@patron.test");
+
+            let user = find_patron(&None, &Some(Sid::from("202300000301")))
+                .await
+                .unwrap();
+            assert_eq!(user.email.as_str(), "one@patron.test");
+            let err = find_patron(&None, &sid).await.unwrap_err();
+            assert!(matches!(err, Error::AmbiguousSid), "{err:?}");
+            let err = find_patron(&None, &Some(Sid::from("202300000399")))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::UserNotExist), "{err:?}");
+            let err = find_patron(&None, &None).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidData(_)), "{err:?}");
+        })
+    }
+}
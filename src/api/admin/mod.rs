@@ -2,7 +2,9 @@ use crate::auth::Token;
 use crate::error::Error;
 use crate::types::Role;
 
+pub mod audit;
 pub mod book;
+pub mod loan;
 pub mod user;
 
 fn is_admin(token: &Token) -> Result<(), Error> {
@@ -12,3 +14,9 @@ fn is_admin(token: &Token) -> Result<(), Error> {
         .then(|| ())
         .ok_or(Error::RoleNotAdmin)
 }
+
+pub(crate) fn is_staff(token: &Token) -> Result<(), Error> {
+    matches!(token.role, Role::Admin | Role::Librarian)
+        .then_some(())
+        .ok_or(Error::RoleNotStaff)
+}
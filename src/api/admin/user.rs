@@ -1,11 +1,19 @@
 use crate::api::admin::is_admin;
 use crate::api::{from_str_option, new_success_resp, to_json, validate, JsonValue};
+use crate::audit::{diff, Action, Audit};
 use crate::auth::Token;
-use crate::db::user::{list as db_list, update as db_update, UpdateUser};
-use crate::error::SUCCESS_CODE;
+use crate::bulk::user::{import, ImportOptions, ImportReport};
+use crate::bulk::{write_rows, Format};
+use crate::db::user::{
+    anonymize as db_anonymize, list as db_list, placeholder_email, query, remove_unused_image,
+    search as db_search, update as db_update, UpdateUser, UserFilter,
+};
+use crate::error::{Error, SUCCESS_CODE};
+use crate::i18n;
 use crate::types::{Email, Password, Role, Sid, Status, Username};
-use poem::web::{Data as PoemData, Json};
-use poem::{handler, Result};
+use poem::http::header;
+use poem::web::{Data as PoemData, Json, Query, RemoteAddr};
+use poem::{handler, IntoResponse, Response, Result};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
@@ -51,6 +59,86 @@ pub async fn list(PoemData(token): PoemData<&Token>) -> Result<JsonValue> {
     }))
 }
 
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SearchUserReq {
+    #[validate(length(max = 10))]
+    username: Option<String>,
+    #[validate(length(max = 255))]
+    email: Option<String>,
+    #[validate(length(max = 12))]
+    sid: Option<String>,
+    #[serde(deserialize_with = "from_str_option", default)]
+    role: Option<Role>,
+    #[serde(deserialize_with = "from_str_option", default)]
+    status: Option<Status>,
+    #[validate(range(min = 1))]
+    page: Option<u64>,
+    #[validate(range(min = 1, max = 100))]
+    page_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchUserResp {
+    code: u32,
+    data: SearchData,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchData {
+    users: Vec<User>,
+    total: u64,
+    page: u64,
+    page_size: u64,
+}
+
+#[handler]
+pub async fn search(
+    Json(req): Json<SearchUserReq>,
+    PoemData(token): PoemData<&Token>,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+    validate(&req)?;
+
+    let filter = UserFilter {
+        username: req.username.filter(|s| !s.is_empty()),
+        email: req.email.filter(|s| !s.is_empty()),
+        sid: req.sid.filter(|s| !s.is_empty()),
+        role: req.role,
+        status: req.status,
+    };
+    let page = req.page.unwrap_or(1);
+    let page_size = req
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE);
+
+    let result = db_search(&filter, page, page_size).await?;
+    let users: Vec<User> = result
+        .records
+        .into_iter()
+        .map(|user| User {
+            name: user.username,
+            sid: user.sid,
+            email: user.email,
+            role: user.role.to_string(),
+            status: user.status.to_string(),
+        })
+        .collect();
+
+    Ok(to_json(SearchUserResp {
+        code: SUCCESS_CODE,
+        data: SearchData {
+            users,
+            total: result.total,
+            page,
+            page_size,
+        },
+    }))
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct UpdateUserReq {
     #[validate]
@@ -65,22 +153,194 @@ pub struct UpdateUserReq {
 pub async fn update(
     Json(req): Json<UpdateUserReq>,
     PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
 ) -> Result<JsonValue> {
     is_admin(token)?;
     validate(&req)?;
+    let old = query(&req.email).await.ok_or(Error::UserNotExist)?;
 
     let user = UpdateUser {
         age: None,
         introduction: None,
         sex: None,
         status: req.status,
+        avatar: None,
         role: req.role.clone(),
+        //管理员设置的密码需由用户在下次登录后修改
+        must_change_password: req.password.is_some().then_some(true),
+        locale: None,
         password: req.password,
         sid: None,
         username: None,
     };
 
-    db_update(&req.email, user).await?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::UserUpdate,
+            req.email.as_str(),
+            diff(&old, &user),
+            async |tx| db_update(tx, &req.email, user).await,
+        )
+        .await?;
+
+    Ok(new_success_resp())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserReq {
+    email: Email,
+}
+
+//停用后用户无法登录，个人信息和借阅记录保持不变
+#[handler]
+pub async fn deactivate(
+    Json(req): Json<UserReq>,
+    PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+    if req.email == token.email {
+        return Err(Error::InvalidData(i18n::message("detail.deactivate_self")).into());
+    }
+    let old = query(&req.email).await.ok_or(Error::UserNotExist)?;
+
+    let user = UpdateUser {
+        status: Some(Status::Disabled),
+        ..Default::default()
+    };
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::UserDeactivate,
+            req.email.as_str(),
+            diff(&old, &user),
+            async |tx| db_update(tx, &req.email, user).await,
+        )
+        .await?;
 
     Ok(new_success_resp())
 }
+
+#[derive(Debug, Serialize)]
+struct AnonymizeResp {
+    code: u32,
+    data: AnonymizeData,
+}
+
+#[derive(Debug, Serialize)]
+struct AnonymizeData {
+    //借阅记录改挂在该占位邮箱下
+    email: Email,
+}
+
+//注销已停用的用户，删除个人信息但保留借阅统计
+#[handler]
+pub async fn anonymize(
+    Json(req): Json<UserReq>,
+    PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    is_admin(token)?;
+
+    let email = placeholder_email();
+    let audit = Audit::new(&token.email, remote_addr);
+    let avatar = audit
+        .record(
+            Action::UserAnonymize,
+            //审计日志中不保留原邮箱
+            email.as_str(),
+            None,
+            async |tx| db_anonymize(tx, &req.email, &email).await,
+        )
+        .await?;
+    if let Some(avatar) = avatar {
+        remove_unused_image(&avatar).await;
+    }
+
+    Ok(to_json(AnonymizeResp {
+        code: SUCCESS_CODE,
+        data: AnonymizeData { email },
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportUserReq {
+    format: Format,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    update_existing: bool,
+    //报告格式，csv 时以附件形式返回
+    report: Option<Format>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportUserResp {
+    code: u32,
+    data: ImportReport,
+}
+
+#[handler]
+pub async fn import_users(
+    Query(req): Query<ImportUserReq>,
+    body: Vec<u8>,
+    PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<Response> {
+    is_admin(token)?;
+
+    let opts = ImportOptions {
+        dry_run: req.dry_run,
+        update_existing: req.update_existing,
+    };
+    //试运行不修改数据，不需要审计
+    let report = if req.dry_run {
+        import(&body, req.format, opts, None).await?
+    } else {
+        //整个文件和其中的每一行各记录一条审计日志
+        let audit = Audit::new(&token.email, remote_addr);
+        audit
+            .record_batch(
+                Action::UserImport,
+                &format!("{} bytes of {}", body.len(), req.format.extension()),
+                None,
+                import(&body, req.format, opts, Some(&audit)),
+            )
+            .await?
+    };
+
+    if req.report == Some(Format::Csv) {
+        let data = write_rows(&report.rows, Format::Csv)?;
+        return Ok(data
+            .with_content_type(Format::Csv.content_type())
+            .with_header(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"accounts.csv\"",
+            )
+            .into_response());
+    }
+
+    Ok(to_json(ImportUserResp {
+        code: SUCCESS_CODE,
+        data: report,
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_search_filter() {
+        let req: SearchUserReq =
+            serde_json::from_str(r#"{"role": "librarian", "status": "enabled"}"#).unwrap();
+        assert_eq!(req.role, Some(Role::Librarian));
+        assert_eq!(req.status, Some(Status::Enabled));
+
+        //无法识别的值视为不筛选
+        let req: SearchUserReq = serde_json::from_str(r#"{"role": "root"}"#).unwrap();
+        assert_eq!(req.role, None);
+    }
+}
@@ -1,10 +1,12 @@
 use crate::api::{new_success_resp, validate, JsonValue};
+use crate::audit::{diff, Action, Audit};
 use crate::auth::Token;
 use crate::db::record::borrow;
 use crate::types::Isbn;
-use poem::web::{Data as PoemData, Json};
+use poem::web::{Data as PoemData, Json, RemoteAddr};
 use poem::{handler, Result};
 use serde::Deserialize;
+use serde_json::{json, Value};
 use validator::Validate;
 
 #[derive(Debug, Deserialize, Validate)]
@@ -17,8 +19,17 @@ pub struct BorrowReq {
 pub async fn borrow_book(
     Json(req): Json<BorrowReq>,
     PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
 ) -> Result<JsonValue> {
     validate(&req)?;
-    borrow(&token.email, &req.isbns).await?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::LoanBorrow,
+            token.email.as_str(),
+            diff(&Value::Null, &json!({ "isbns": req.isbns })),
+            async |tx| borrow(tx, &token.email, &req.isbns, None).await,
+        )
+        .await?;
     Ok(new_success_resp())
 }
@@ -1,6 +1,7 @@
+use crate::api::book::{to_items, BookItem};
 use crate::api::{to_json, JsonValue};
 use crate::auth::Token;
-use crate::db::book::{list, Book};
+use crate::db::book::list;
 use crate::error::SUCCESS_CODE;
 use poem::web::Data as PoemData;
 use poem::{handler, Result};
@@ -14,15 +15,17 @@ struct GetListResp {
 
 #[derive(Debug, Serialize)]
 struct Data {
-    items: Vec<Book>,
+    items: Vec<BookItem>,
 }
 
 #[handler]
 pub async fn get_list(PoemData(_token): PoemData<&Token>) -> Result<JsonValue> {
-    let v = list().await?;
+    let v = list(false).await?;
 
     Ok(to_json(GetListResp {
         code: SUCCESS_CODE,
-        data: Data { items: v },
+        data: Data {
+            items: to_items(v).await?,
+        },
     }))
 }
@@ -4,3 +4,97 @@ pub mod list;
 pub mod return_book;
 pub mod return_record;
 pub mod search;
+
+use crate::db::book::{list_contributors, list_subjects, Book};
+use crate::error::Error;
+use crate::types::{
+    isbn_hyphenated, Author, BookStatus, Bookname, CallNumber, ContributorRole, Description,
+    Edition, Isbn, Language, PageCount, Press, PublicationYear, ShelfLocation, Stock, Subject,
+    WithdrawnReason,
+};
+use crate::upload::{image_url, thumbnail_url};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize)]
+pub struct ContributorItem {
+    name: Author,
+    role: ContributorRole,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookItem {
+    name: Bookname,
+    author: Author,
+    #[serde(serialize_with = "isbn_hyphenated")]
+    isbn: Isbn,
+    press: Press,
+    stock: Stock,
+    remain: Stock,
+    publication_year: Option<PublicationYear>,
+    edition: Option<Edition>,
+    language: Option<Language>,
+    page_count: Option<PageCount>,
+    description: Option<Description>,
+    call_number: Option<CallNumber>,
+    shelf_location: Option<ShelfLocation>,
+    cover: Option<String>,
+    cover_thumbnail: Option<String>,
+    status: BookStatus,
+    withdrawn_date: Option<NaiveDateTime>,
+    withdrawn_reason: Option<WithdrawnReason>,
+    contributors: Vec<ContributorItem>,
+    subjects: Vec<Subject>,
+}
+
+//补充责任者和主题分类，未登记责任者的书籍以第一作者代替
+pub async fn to_items(books: Vec<Book>) -> Result<Vec<BookItem>, Error> {
+    let isbns: Vec<Isbn> = books.iter().map(|book| book.isbn.clone()).collect();
+
+    let mut contributors: HashMap<Isbn, Vec<ContributorItem>> = HashMap::new();
+    for c in list_contributors(&isbns).await? {
+        contributors
+            .entry(c.isbn)
+            .or_default()
+            .push(ContributorItem {
+                name: c.name,
+                role: c.role,
+            });
+    }
+    let mut subjects: HashMap<Isbn, Vec<Subject>> = HashMap::new();
+    for s in list_subjects(&isbns).await? {
+        subjects.entry(s.isbn).or_default().push(s.subject);
+    }
+
+    Ok(books
+        .into_iter()
+        .map(|book| BookItem {
+            contributors: contributors.remove(&book.isbn).unwrap_or_else(|| {
+                vec![ContributorItem {
+                    name: book.author.clone(),
+                    role: ContributorRole::Author,
+                }]
+            }),
+            subjects: subjects.remove(&book.isbn).unwrap_or_default(),
+            name: book.name,
+            author: book.author,
+            isbn: book.isbn,
+            press: book.press,
+            stock: book.stock,
+            remain: book.remain,
+            publication_year: book.publication_year,
+            edition: book.edition,
+            language: book.language,
+            page_count: book.page_count,
+            description: book.description,
+            call_number: book.call_number,
+            shelf_location: book.shelf_location,
+            cover: book.cover.as_deref().map(image_url),
+            cover_thumbnail: book.cover.as_deref().map(thumbnail_url),
+            status: book.status,
+            withdrawn_date: book.withdrawn_date,
+            withdrawn_reason: book.withdrawn_reason,
+        })
+        .collect())
+}
@@ -1,10 +1,12 @@
 use crate::api::{new_success_resp, validate, JsonValue};
+use crate::audit::{diff, Action, Audit};
 use crate::auth::Token;
 use crate::db::record::return_book as db_return_book;
 use crate::types::Isbn;
-use poem::web::{Data as PoemData, Json};
+use poem::web::{Data as PoemData, Json, RemoteAddr};
 use poem::{handler, Result};
 use serde::Deserialize;
+use serde_json::{json, Value};
 use validator::Validate;
 
 #[derive(Debug, Deserialize, Validate)]
@@ -17,9 +19,18 @@ pub struct ReturnReq {
 pub async fn return_book(
     Json(req): Json<ReturnReq>,
     PoemData(token): PoemData<&Token>,
+    remote_addr: &RemoteAddr,
 ) -> Result<JsonValue> {
     validate(&req)?;
-    db_return_book(&token.email, &req.isbns).await?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::LoanReturn,
+            token.email.as_str(),
+            diff(&Value::Null, &json!({ "isbns": req.isbns })),
+            async |tx| db_return_book(tx, &token.email, &req.isbns, None).await,
+        )
+        .await?;
 
     Ok(new_success_resp())
 }
@@ -2,7 +2,7 @@ use crate::api::{to_json, JsonValue};
 use crate::auth::Token;
 use crate::db::record::list_return_book;
 use crate::error::SUCCESS_CODE;
-use crate::types::{Bookname, Isbn};
+use crate::types::{isbn_hyphenated, Bookname, Isbn};
 use chrono::NaiveDateTime;
 use poem::web::Data as PoemData;
 use poem::{handler, Result};
@@ -22,6 +22,7 @@ struct Data {
 #[derive(Debug, Serialize)]
 struct Item {
     name: Bookname,
+    #[serde(serialize_with = "isbn_hyphenated")]
     isbn: Isbn,
     borrowed_date: NaiveDateTime,
     return_date: Option<NaiveDateTime>,
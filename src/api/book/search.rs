@@ -1,6 +1,8 @@
+use crate::api::admin::is_staff;
+use crate::api::book::{to_items, BookItem};
 use crate::api::{to_json, validate, JsonValue};
 use crate::auth::Token;
-use crate::db::book::{fuzzy_query, Book};
+use crate::db::book::{fuzzy_query, BookFilter};
 use crate::error::SUCCESS_CODE;
 use poem::web::{Data as PoemData, Json};
 use poem::{handler, Result};
@@ -15,28 +17,60 @@ struct SearchListResp {
 
 #[derive(Debug, Serialize)]
 struct Data {
-    items: Vec<Book>,
+    items: Vec<BookItem>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
 pub struct SearchListReq {
-    #[validate(length(max = 20))]
+    #[validate(length(max = 50))]
     name: Option<String>,
-    #[validate(length(max = 13))]
+    #[validate(length(max = 17))]
     isbn: Option<String>,
-    #[validate(length(max = 20))]
+    #[validate(length(max = 100))]
     author: Option<String>,
+    #[validate(length(max = 100))]
+    press: Option<String>,
+    #[validate(length(max = 50))]
+    subject: Option<String>,
+    #[validate(length(max = 20))]
+    language: Option<String>,
+    publication_year: Option<u32>,
+    #[validate(length(max = 50))]
+    call_number: Option<String>,
+    //仅对管理员和图书管理员生效
+    include_withdrawn: Option<bool>,
+}
+
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.filter(|s| !s.is_empty())
 }
 
 #[handler]
 pub async fn search_list(
     Json(req): Json<SearchListReq>,
-    PoemData(_token): PoemData<&Token>,
+    PoemData(token): PoemData<&Token>,
 ) -> Result<JsonValue> {
     validate(&req)?;
-    let v = fuzzy_query(&req.name, &req.isbn, &req.author).await?;
+    //允许输入带连字符的部分 ISBN
+    let isbn = req
+        .isbn
+        .map(|s| s.chars().filter(|c| !matches!(c, '-' | ' ')).collect());
+    let filter = BookFilter {
+        name: non_empty(req.name),
+        isbn: non_empty(isbn),
+        author: non_empty(req.author),
+        press: non_empty(req.press),
+        subject: non_empty(req.subject),
+        language: non_empty(req.language),
+        publication_year: req.publication_year,
+        call_number: non_empty(req.call_number),
+        include_withdrawn: req.include_withdrawn.unwrap_or_default() && is_staff(token).is_ok(),
+    };
+    let v = fuzzy_query(&filter).await?;
     Ok(to_json(SearchListResp {
         code: SUCCESS_CODE,
-        data: Data { items: v },
+        data: Data {
+            items: to_items(v).await?,
+        },
     }))
 }
@@ -0,0 +1,124 @@
+use crate::db::{migration, missing_tables, ping};
+use crate::embed::Assets;
+use poem::http::StatusCode;
+use poem::web::Json;
+use poem::{handler, IntoResponse, Response};
+use serde::Serialize;
+use std::time::Instant;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            status: Status::Ok,
+            latency_ms: None,
+            error: None,
+        }
+    }
+
+    fn failed(error: String) -> Self {
+        Self {
+            status: Status::Unavailable,
+            latency_ms: None,
+            error: Some(error),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self.status, Status::Ok)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Checks {
+    database: Check,
+    schema: Check,
+    assets: Check,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResp {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<Checks>,
+}
+
+//存活探针：进程能处理请求即可
+#[handler]
+pub fn healthz() -> Response {
+    Json(HealthResp {
+        status: Status::Ok,
+        checks: None,
+    })
+    .into_response()
+}
+
+//就绪探针：数据库可连接、数据表齐全、前端资源已嵌入，否则返回 503
+#[handler]
+pub async fn readyz() -> Response {
+    let start = Instant::now();
+    let database = match ping().await {
+        Ok(()) => Check {
+            latency_ms: Some(start.elapsed().as_secs_f64() * 1000.0),
+            ..Check::ok()
+        },
+        Err(e) => Check::failed(e.to_string()),
+    };
+
+    let schema = if database.is_ok() {
+        //表存在但列不全时同样未就绪，按迁移版本判断
+        let missing = missing_tables().await;
+        match migration::pending().await {
+            _ if !missing.is_empty() => {
+                Check::failed(format!("missing tables: {}", missing.join(", ")))
+            }
+            Ok(pending) if pending.is_empty() => Check::ok(),
+            Ok(pending) => Check::failed(format!("pending migrations: {pending:?}")),
+            Err(e) => Check::failed(e.to_string()),
+        }
+    } else {
+        Check::failed("database is unavailable".to_string())
+    };
+
+    let assets = if Assets::get("index.html").is_some() {
+        Check::ok()
+    } else {
+        Check::failed("index.html is not embedded".to_string())
+    };
+
+    let ready = database.is_ok() && schema.is_ok() && assets.is_ok();
+    let resp = HealthResp {
+        status: if ready {
+            Status::Ok
+        } else {
+            Status::Unavailable
+        },
+        checks: Some(Checks {
+            database,
+            schema,
+            assets,
+        }),
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Json(resp).with_status(status).into_response()
+}
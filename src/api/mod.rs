@@ -1,8 +1,10 @@
 pub mod admin;
 pub mod book;
+pub mod health;
 pub mod user;
 
 use crate::error::{Error, SUCCESS_CODE};
+use crate::upload::{image_url, thumbnail_url};
 
 use poem::web::Json;
 use serde::de::{self, Deserializer};
@@ -19,32 +21,39 @@ fn to_json<T: Serialize>(v: T) -> JsonValue {
 }
 
 #[derive(Debug, Serialize)]
-struct ErrResp {
+struct SuccessResp {
     code: u32,
-    message: String,
+}
+
+pub fn new_success_resp() -> JsonValue {
+    to_json(SuccessResp { code: SUCCESS_CODE })
 }
 
 #[derive(Debug, Serialize)]
-struct SuccessResp {
+struct ImageResp {
     code: u32,
+    data: ImageData,
 }
 
-pub fn new_success_resp() -> JsonValue {
-    to_json(SuccessResp { code: SUCCESS_CODE })
+#[derive(Debug, Serialize)]
+struct ImageData {
+    url: String,
+    thumbnail: String,
+}
+
+//上传图片成功后返回原图和缩略图地址
+fn new_image_resp(name: &str) -> JsonValue {
+    to_json(ImageResp {
+        code: SUCCESS_CODE,
+        data: ImageData {
+            url: image_url(name),
+            thumbnail: thumbnail_url(name),
+        },
+    })
 }
 
 pub fn validate(data: &impl Validate) -> Result<(), Error> {
-    match data.validate() {
-        Ok(_) => Ok(()),
-        Err(errs) => {
-            let s = errs.to_string();
-            if &s[s.len() - 4..] == "[{}]" {
-                Err(Error::InvalidData(s[..s.len() - 4].into()))
-            } else {
-                Err(Error::InvalidData(s))
-            }
-        }
-    }
+    data.validate().map_err(Error::from)
 }
 
 pub fn from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
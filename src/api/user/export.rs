@@ -0,0 +1,104 @@
+use crate::auth::Token;
+use crate::db::record::{due_date, list_borrowed_book, list_return_book, now_with_timezone};
+use crate::db::user::query;
+use crate::error::Error;
+use crate::types::{isbn_hyphenated, Age, Bookname, Email, Introduction, Isbn, Sex, Sid, Username};
+use crate::upload::image_url;
+use chrono::NaiveDateTime;
+use log::debug;
+use poem::http::header;
+use poem::web::Data as PoemData;
+use poem::{handler, IntoResponse, Response, Result};
+use serde::Serialize;
+
+//个人数据导出，以 JSON 附件形式返回当前用户的全部个人信息和借阅记录
+#[derive(Debug, Serialize)]
+struct Archive {
+    generated_at: NaiveDateTime,
+    profile: Profile,
+    borrowed: Vec<BorrowedItem>,
+    returned: Vec<ReturnedItem>,
+}
+
+#[derive(Debug, Serialize)]
+struct Profile {
+    name: Username,
+    email: Email,
+    sid: Sid,
+    age: Age,
+    sex: Sex,
+    introduction: Introduction,
+    role: String,
+    status: String,
+    avatar: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BorrowedItem {
+    name: Bookname,
+    #[serde(serialize_with = "isbn_hyphenated")]
+    isbn: Isbn,
+    borrowed_date: NaiveDateTime,
+    due_date: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct ReturnedItem {
+    name: Bookname,
+    #[serde(serialize_with = "isbn_hyphenated")]
+    isbn: Isbn,
+    borrowed_date: NaiveDateTime,
+    return_date: Option<NaiveDateTime>,
+}
+
+#[handler]
+pub async fn export(PoemData(token): PoemData<&Token>) -> Result<Response> {
+    let user = query(&token.email).await.ok_or(Error::InvalidlToken)?;
+    let borrowed = list_borrowed_book(&token.email).await?;
+    let returned = list_return_book(&token.email).await?;
+
+    let archive = Archive {
+        generated_at: now_with_timezone(),
+        profile: Profile {
+            name: user.username,
+            email: user.email,
+            sid: user.sid,
+            age: user.age,
+            sex: user.sex,
+            introduction: user.introduction,
+            role: user.role.to_string(),
+            status: user.status.to_string(),
+            avatar: user.avatar.as_deref().map(image_url),
+        },
+        borrowed: borrowed
+            .into_iter()
+            .map(|book| BorrowedItem {
+                due_date: due_date(&book.borrowed_date),
+                name: book.book_name,
+                isbn: book.isbn,
+                borrowed_date: book.borrowed_date,
+            })
+            .collect(),
+        returned: returned
+            .into_iter()
+            .map(|book| ReturnedItem {
+                name: book.book_name,
+                isbn: book.isbn,
+                borrowed_date: book.borrowed_date,
+                return_date: book.return_date,
+            })
+            .collect(),
+    };
+
+    let data = serde_json::to_vec_pretty(&archive).map_err(|e| {
+        debug!("{e}");
+        Error::InternalErr
+    })?;
+    Ok(data
+        .with_content_type("application/json")
+        .with_header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"user-data.json\"",
+        )
+        .into_response())
+}
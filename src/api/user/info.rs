@@ -2,11 +2,15 @@ use crate::api::{to_json, JsonValue};
 use crate::auth::Token;
 use crate::db::user::query;
 use crate::error::{Error, SUCCESS_CODE};
+use crate::i18n::Locale;
 use crate::types::{Age, Email, Introduction, Sex, Sid, Username};
+use crate::upload::image_url;
 use poem::web::Data as PoemData;
 use poem::{handler, Result};
 use serde::{Deserialize, Serialize};
 
+const DEFAULT_AVATAR: &str = "https://wpimg.wallstcn.com/f778738c-e4f8-4870-b634-56703b4acafe.gif";
+
 #[derive(Debug, Deserialize, Serialize)]
 struct GetInfoResp {
     code: u32,
@@ -23,6 +27,8 @@ struct Data {
     roles: String,
     introduction: Introduction,
     avatar: String,
+    //未设置时为 null
+    locale: Option<Locale>,
 }
 
 #[handler]
@@ -39,8 +45,11 @@ pub async fn get_info(PoemData(token): PoemData<&Token>) -> Result<JsonValue> {
             sid: user.sid,
             roles: user.role.to_string(),
             introduction: user.introduction,
-            avatar: "https://wpimg.wallstcn.com/f778738c-e4f8-4870-b634-56703b4acafe.gif"
-                .to_string(),
+            avatar: user
+                .avatar
+                .as_deref()
+                .map_or_else(|| DEFAULT_AVATAR.to_string(), image_url),
+            locale: user.locale,
         },
     }))
 }
@@ -25,6 +25,8 @@ struct LoginResp {
 #[derive(Debug, Serialize)]
 struct Data {
     token: String,
+    //为 true 时前端应引导用户修改密码，其它接口在修改前不可用
+    must_change_password: bool,
 }
 
 #[handler]
@@ -41,6 +43,9 @@ pub async fn login(Json(req): Json<LoginReq>) -> Result<JsonValue> {
 
     Ok(to_json(LoginResp {
         code: SUCCESS_CODE,
-        data: Data { token },
+        data: Data {
+            token,
+            must_change_password: user.must_change_password,
+        },
     }))
 }
@@ -1,3 +1,4 @@
+pub mod export;
 pub mod info;
 pub mod login;
 pub mod logout;
@@ -1,10 +1,12 @@
 use crate::api::{new_success_resp, validate, JsonValue};
+use crate::audit::{diff, Action, Audit};
 use crate::db::user::{add, exist, User};
 use crate::error::{Error, SUCCESS_CODE};
 use crate::types::{Age, Email, Introduction, Password, Role, Sex, Sid, Status, Username};
-use poem::web::Json;
+use poem::web::{Json, RemoteAddr};
 use poem::{handler, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use validator::Validate;
 
 #[derive(Debug, Deserialize, Validate)]
@@ -37,7 +39,7 @@ impl Default for RegisterResp {
 }
 
 #[handler]
-pub async fn register(Json(req): Json<RegisterReq>) -> Result<JsonValue> {
+pub async fn register(Json(req): Json<RegisterReq>, remote_addr: &RemoteAddr) -> Result<JsonValue> {
     validate(&req)?;
     if exist(&req.email).await.is_some() {
         return Err(Error::UserAlreadyExist.into());
@@ -53,11 +55,28 @@ pub async fn register(Json(req): Json<RegisterReq>) -> Result<JsonValue> {
         sex: req.sex,
         role: Role::User,
         status: Status::Enabled,
+        avatar: None,
+        must_change_password: false,
+        locale: None,
     };
 
-    if add(user, None).await.is_some() {
-        Ok(new_success_resp())
-    } else {
-        Err(Error::FailedToRegister.into())
-    }
+    let audit = Audit::new(&user.email, remote_addr);
+    audit
+        .record(
+            Action::UserRegister,
+            user.email.as_str(),
+            //个人资料不写入审计日志
+            diff(
+                &Value::Null,
+                &json!({ "email": user.email, "role": user.role }),
+            ),
+            async |tx| {
+                add(tx, user.clone(), None)
+                    .await
+                    .ok_or(Error::FailedToRegister)
+            },
+        )
+        .await?;
+
+    Ok(new_success_resp())
 }
@@ -1,9 +1,12 @@
-use crate::api::{new_success_resp, validate, JsonValue};
+use crate::api::{new_image_resp, new_success_resp, validate, JsonValue};
+use crate::audit::{diff, Action, Audit};
 use crate::auth::Token;
-use crate::db::user::{update as db_update, verify, UpdateUser};
+use crate::db::user::{query, update as db_update, verify, UpdateUser};
 use crate::error::Error;
+use crate::i18n::Locale;
 use crate::types::{Age, Introduction, Password, Sex, Sid, Username};
-use poem::web::{Data, Json};
+use crate::upload::{read_image, save_image};
+use poem::web::{Data, Json, Multipart, RemoteAddr};
 use poem::{handler, Result};
 use serde::Deserialize;
 use validator::Validate;
@@ -34,8 +37,10 @@ pub struct ChangePasswordReq {
 pub async fn update(
     Json(req): Json<UpdateUserReq>,
     Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
 ) -> Result<JsonValue> {
     validate(&req)?;
+    let old = query(&token.email).await.ok_or(Error::InvalidlToken)?;
     let user = UpdateUser {
         username: Some(req.username),
         password: None,
@@ -45,9 +50,51 @@ pub async fn update(
         sex: Some(req.sex),
         role: None,
         status: None,
+        avatar: None,
+        must_change_password: None,
+        locale: None,
     };
 
-    db_update(&token.email, user).await?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::UserUpdate,
+            token.email.as_str(),
+            diff(&old, &user),
+            async |tx| db_update(tx, &token.email, user).await,
+        )
+        .await?;
+
+    Ok(new_success_resp())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetLocaleReq {
+    //为 null 时清除设置，按 Accept-Language 选择语言
+    locale: Option<Locale>,
+}
+
+#[handler]
+pub async fn set_locale(
+    Json(req): Json<SetLocaleReq>,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    let old = query(&token.email).await.ok_or(Error::InvalidlToken)?;
+    let user = UpdateUser {
+        locale: Some(req.locale.map_or("", Locale::tag).to_string()),
+        ..Default::default()
+    };
+
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::UserUpdate,
+            token.email.as_str(),
+            diff(&old, &user),
+            async |tx| db_update(tx, &token.email, user).await,
+        )
+        .await?;
 
     Ok(new_success_resp())
 }
@@ -56,9 +103,10 @@ pub async fn update(
 pub async fn change_password(
     Json(req): Json<ChangePasswordReq>,
     Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
 ) -> Result<JsonValue> {
     validate(&req)?;
-    verify(&token.email, &req.old_password)
+    let old = verify(&token.email, &req.old_password)
         .await
         .ok_or(Error::InvalidPassword)?;
 
@@ -71,9 +119,46 @@ pub async fn change_password(
         sex: None,
         role: None,
         status: None,
+        avatar: None,
+        must_change_password: Some(false),
+        locale: None,
     };
 
-    db_update(&token.email, user).await?;
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::UserChangePassword,
+            token.email.as_str(),
+            diff(&old, &user),
+            async |tx| db_update(tx, &token.email, user).await,
+        )
+        .await?;
 
     Ok(new_success_resp())
 }
+
+#[handler]
+pub async fn upload_avatar(
+    multipart: Multipart,
+    Data(token): Data<&Token>,
+    remote_addr: &RemoteAddr,
+) -> Result<JsonValue> {
+    let old = query(&token.email).await.ok_or(Error::InvalidlToken)?;
+    let name = save_image(read_image(multipart).await?).await?;
+    let user = UpdateUser {
+        avatar: Some(name.clone()),
+        ..Default::default()
+    };
+
+    let audit = Audit::new(&token.email, remote_addr);
+    audit
+        .record(
+            Action::UserAvatar,
+            token.email.as_str(),
+            diff(&old, &user),
+            async |tx| db_update(tx, &token.email, user).await,
+        )
+        .await?;
+
+    Ok(new_image_resp(&name))
+}
@@ -0,0 +1,278 @@
+//审计日志：所有修改数据的接口在修改的事务中先写入一条记录，写入失败则放弃本次修改
+use crate::db::audit::{save, AuditLog};
+use crate::db::record::now_with_timezone;
+use crate::db::{transaction, Tx};
+use crate::error::Error;
+use crate::types::Email;
+use log::{debug, warn};
+use poem::web::RemoteAddr;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::future::Future;
+
+//这些字段在审计日志中只记录是否发生了变化，不保留密码和个人资料
+const REDACTED_FIELDS: [&str; 4] = ["password", "introduction", "age", "sex"];
+const REDACTED: &str = "******";
+const MAX_TARGET_LEN: usize = 255;
+const MAX_ERROR_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    BookAdd,
+    BookUpdate,
+    BookWithdraw,
+    BookRestore,
+    BookImport,
+    BookCover,
+    UserRegister,
+    UserUpdate,
+    UserChangePassword,
+    UserAvatar,
+    UserDeactivate,
+    UserAnonymize,
+    UserImport,
+    LoanBorrow,
+    LoanReturn,
+    LoanCheckout,
+    LoanCheckin,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::BookAdd => "book.add",
+            Action::BookUpdate => "book.update",
+            Action::BookWithdraw => "book.withdraw",
+            Action::BookRestore => "book.restore",
+            Action::BookImport => "book.import",
+            Action::BookCover => "book.cover",
+            Action::UserRegister => "user.register",
+            Action::UserUpdate => "user.update",
+            Action::UserChangePassword => "user.change_password",
+            Action::UserAvatar => "user.avatar",
+            Action::UserDeactivate => "user.deactivate",
+            Action::UserAnonymize => "user.anonymize",
+            Action::UserImport => "user.import",
+            Action::LoanBorrow => "loan.borrow",
+            Action::LoanReturn => "loan.return",
+            Action::LoanCheckout => "loan.checkout",
+            Action::LoanCheckin => "loan.checkin",
+        }
+    }
+}
+
+pub fn snapshot<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+//比较修改前后的字段，after 中为 null 的字段视为未修改
+pub fn diff<B: Serialize, A: Serialize>(before: &B, after: &A) -> Option<Value> {
+    let before = snapshot(before);
+    let after = match snapshot(after) {
+        Value::Object(after) => after,
+        _ => return None,
+    };
+
+    let mut changes = Map::new();
+    for (key, new) in after {
+        let old = before.get(&key).cloned().unwrap_or(Value::Null);
+        if new.is_null() || old == new {
+            continue;
+        }
+        let (old, new) = if REDACTED_FIELDS.contains(&key.as_str()) {
+            let old = (!old.is_null()).then(|| Value::from(REDACTED));
+            (old.unwrap_or(Value::Null), Value::from(REDACTED))
+        } else {
+            (old, new)
+        };
+        let mut change = Map::new();
+        change.insert("before".into(), old);
+        change.insert("after".into(), new);
+        changes.insert(key, Value::Object(change));
+    }
+
+    (!changes.is_empty()).then_some(Value::Object(changes))
+}
+
+pub struct Audit {
+    actor: Email,
+    ip: Option<String>,
+}
+
+impl Audit {
+    pub fn new(actor: &Email, remote_addr: &RemoteAddr) -> Self {
+        Self {
+            actor: actor.clone(),
+            ip: remote_addr
+                .as_socket_addr()
+                .map(|addr| addr.ip().to_string()),
+        }
+    }
+
+    fn log(&self, action: Action, target: &str, diff: Option<Value>) -> AuditLog {
+        AuditLog {
+            id: None,
+            actor: self.actor.clone(),
+            action: action.as_str().to_string(),
+            target: target.chars().take(MAX_TARGET_LEN).collect(),
+            diff: diff.map(|diff| diff.to_string()),
+            ip: self.ip.clone(),
+            created_date: now_with_timezone(),
+            failure_of: None,
+            error: None,
+        }
+    }
+
+    //审计日志和修改在同一事务中写入；修改失败时回滚，再单独写入该记录和指向它的失败记录
+    pub async fn record<T, F>(
+        &self,
+        action: Action,
+        target: &str,
+        diff: Option<Value>,
+        mutation: F,
+    ) -> Result<T, Error>
+    where
+        F: AsyncFnOnce(&mut Tx) -> Result<T, Error>,
+    {
+        let log = self.log(action, target, diff);
+        let map_err = |e: rbatis::Error| {
+            debug!("{e}");
+            Error::DbError
+        };
+        let mut tx = Tx::begin().await.map_err(map_err)?;
+        let id = save(&mut tx, &log).await?;
+        let result = match mutation(&mut tx).await {
+            Ok(value) => tx.commit().await.map(|()| value).map_err(map_err),
+            Err(e) => {
+                if let Err(e) = tx.rollback().await {
+                    warn!("failed to roll back transaction: {e}");
+                }
+                Err(e)
+            }
+        };
+        match &result {
+            Ok(_) => debug!("audit log {id}: {} {}", log.action, log.target),
+            Err(e) => record_failure(log, e).await,
+        }
+        result
+    }
+
+    //批量导入逐行提交，每一行另有审计日志，整个文件的记录无法与修改放在同一事务中，先单独写入
+    pub async fn record_batch<T, F>(
+        &self,
+        action: Action,
+        target: &str,
+        diff: Option<Value>,
+        mutation: F,
+    ) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        let mut log = self.log(action, target, diff);
+        log.id = Some(transaction(async |tx| save(tx, &log).await).await?);
+
+        let result = mutation.await;
+        if let Err(e) = &result {
+            record_failure(log, e).await;
+        }
+        result
+    }
+}
+
+async fn record_failure(log: AuditLog, e: &Error) {
+    let error = e.to_string().chars().take(MAX_ERROR_LEN).collect();
+    let result = transaction(async |tx| {
+        let id = match log.id {
+            Some(id) => id,
+            None => save(tx, &log).await?,
+        };
+        let failure = AuditLog {
+            id: None,
+            diff: None,
+            created_date: now_with_timezone(),
+            failure_of: Some(id),
+            error: Some(error),
+            ..log
+        };
+        save(tx, &failure).await
+    })
+    .await;
+    if let Err(e) = result {
+        warn!("failed to record failure of audit log: {e}");
+    }
+}
+
+//批量导入也可以由命令行执行，这时没有操作者，不写审计日志
+pub async fn record_if<T, F>(
+    audit: Option<&Audit>,
+    action: Action,
+    target: &str,
+    diff: Option<Value>,
+    mutation: F,
+) -> Result<T, Error>
+where
+    F: AsyncFnOnce(&mut Tx) -> Result<T, Error>,
+{
+    match audit {
+        Some(audit) => audit.record(action, target, diff, mutation).await,
+        None => transaction(mutation).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::book::{add, query_by_isbn};
+    use crate::db::testing::{audit, audit_logs as logs, book, run};
+    use serde_json::json;
+
+    #[test]
+    fn redact_personal_fields() {
+        let before = json!({ "username": "a", "age": "20", "sex": "male" });
+        let after = json!({ "username": "b", "age": "21", "password": "secret", "sex": null });
+        assert_eq!(
+            diff(&before, &after),
+            Some(json!({
+                "username": { "before": "a", "after": "b" },
+                "age": { "before": REDACTED, "after": REDACTED },
+                "password": { "before": null, "after": REDACTED },
+            }))
+        );
+        assert_eq!(diff(&before, &before), None);
+    }
+
+    #[test]
+    fn record_with_mutation() {
+        run(async {
+            let audit = audit("staff@audit.test");
+            let added = book("9781869800017", 1);
+            let isbn = added.isbn.clone();
+            audit
+                .record(Action::BookAdd, isbn.as_str(), None, async |tx| {
+                    add(tx, added, &[], &[]).await
+                })
+                .await
+                .unwrap();
+            let log = &logs(isbn.as_str()).await[0];
+            assert_eq!(log.action, "book.add");
+            assert_eq!(log.ip.as_deref(), Some("10.0.0.1"));
+
+            //修改失败时回滚，但仍留下记录和指向它的失败记录
+            let failed = book("9787802031005", 1);
+            let isbn = failed.isbn.clone();
+            let err = audit
+                .record(Action::BookAdd, isbn.as_str(), None, async |tx| {
+                    add(tx, failed, &[], &[]).await?;
+                    Err::<(), _>(Error::StockIsntEnough)
+                })
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::StockIsntEnough), "{err:?}");
+            assert!(query_by_isbn(&isbn).await.is_none());
+            let logs = logs(isbn.as_str()).await;
+            assert_eq!(logs.len(), 2);
+            assert_eq!(logs[1].failure_of, logs[0].id);
+            assert!(logs[1].error.is_some());
+        })
+    }
+}
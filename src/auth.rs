@@ -7,7 +7,7 @@ lazy_static::lazy_static! {
     static ref KEY:HS256Key = HS256Key::generate();
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Token {
     pub email: Email,
     pub role: Role,
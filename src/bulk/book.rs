@@ -0,0 +1,508 @@
+use super::{marc, parse_rows, write_rows, Format, RowError};
+use crate::api::validate;
+use crate::audit::{self, diff, record_if, snapshot, Audit};
+use crate::db::book::{
+    add, list, list_contributors, list_subjects, query_by_isbn, update, Book, Contributor,
+    UpdateBook,
+};
+use crate::db::Tx;
+use crate::error::Error;
+use crate::i18n;
+use crate::marc::Record;
+use crate::types::{
+    Author, BookStatus, Bookname, CallNumber, ContributorRole, Description, Edition, Isbn,
+    Language, PageCount, Press, PublicationYear, ShelfLocation, Stock, Subject,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use validator::Validate;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Validate)]
+pub struct BookRow {
+    #[validate]
+    pub name: Bookname,
+    #[validate]
+    pub isbn: Isbn,
+    #[validate]
+    pub author: Author,
+    #[validate]
+    pub press: Press,
+    #[validate]
+    pub stock: Stock,
+    #[validate]
+    #[serde(default)]
+    pub publication_year: Option<PublicationYear>,
+    #[validate]
+    #[serde(default)]
+    pub edition: Option<Edition>,
+    #[validate]
+    #[serde(default)]
+    pub language: Option<Language>,
+    #[validate]
+    #[serde(default)]
+    pub page_count: Option<PageCount>,
+    #[validate]
+    #[serde(default)]
+    pub description: Option<Description>,
+    #[validate]
+    #[serde(default)]
+    pub call_number: Option<CallNumber>,
+    #[validate]
+    #[serde(default)]
+    pub shelf_location: Option<ShelfLocation>,
+    //CSV/JSON 中以 `; ` 分隔，责任者写作 `姓名 (角色)`；非空时整体替换原有数据
+    #[validate]
+    #[serde(default, with = "contributor_list")]
+    pub contributors: Vec<ContributorRow>,
+    #[validate]
+    #[serde(default, with = "subject_list")]
+    pub subjects: Vec<Subject>,
+}
+
+const LIST_SEPARATOR: char = ';';
+
+//未写角色或括号中不是已知角色时按著者处理，姓名保持原样
+fn parse_contributor(s: &str) -> ContributorRow {
+    let role = s
+        .strip_suffix(')')
+        .and_then(|s| s.rsplit_once(" ("))
+        .and_then(|(name, role)| {
+            ContributorRole::ALL
+                .into_iter()
+                .find(|r| r.as_str() == role)
+                .map(|role| (name, role))
+        });
+    match role {
+        Some((name, role)) => ContributorRow {
+            name: Author::from(name.trim()),
+            role,
+        },
+        None => ContributorRow {
+            name: Author::from(s),
+            role: ContributorRole::Author,
+        },
+    }
+}
+
+fn split_list(s: &str) -> impl Iterator<Item = &str> {
+    s.split(LIST_SEPARATOR)
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+fn join_list<S: Serializer>(
+    items: impl Iterator<Item = String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(
+        &items
+            .collect::<Vec<_>>()
+            .join(&format!("{LIST_SEPARATOR} ")),
+    )
+}
+
+mod contributor_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        list: &[ContributorRow],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        join_list(
+            list.iter()
+                .map(|c| format!("{} ({})", c.name.as_str(), c.role.as_str())),
+            serializer,
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<ContributorRow>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(split_list(&s).map(parse_contributor).collect())
+    }
+}
+
+mod subject_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(list: &[Subject], serializer: S) -> Result<S::Ok, S::Error> {
+        join_list(list.iter().map(|s| s.as_str().to_string()), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Subject>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(split_list(&s).map(Subject::from).collect())
+    }
+}
+
+#[derive(Debug, Clone, Validate)]
+pub struct ContributorRow {
+    #[validate]
+    pub name: Author,
+    pub role: ContributorRole,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportOptions {
+    //只校验不写入
+    pub dry_run: bool,
+    //ISBN 已存在时更新书籍信息，否则报错
+    pub upsert: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub failed: usize,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Create,
+    Update,
+}
+
+pub async fn import(
+    data: &[u8],
+    format: Format,
+    opts: ImportOptions,
+    audit: Option<&Audit>,
+) -> Result<ImportReport, Error> {
+    let rows = match format {
+        Format::Marc | Format::MarcXml => marc::parse(data, format)?,
+        Format::Csv | Format::Json => parse_rows::<BookRow>(data, format)?,
+    };
+    let mut report = ImportReport {
+        dry_run: opts.dry_run,
+        total: rows.len(),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+
+    for (i, row) in rows.into_iter().enumerate() {
+        let (key, result) = match row {
+            Ok(row) => (
+                Some(row.isbn.as_str().to_string()),
+                import_row(row, &mut seen, opts, audit).await,
+            ),
+            Err(e) => (None, Err(Error::InvalidData(e))),
+        };
+
+        match result {
+            Ok(Action::Create) => report.created += 1,
+            Ok(Action::Update) => report.updated += 1,
+            Err(e) => {
+                report.failed += 1;
+                report.errors.push(RowError::new(i + 1, key, &e));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+async fn import_row(
+    row: BookRow,
+    seen: &mut HashSet<Isbn>,
+    opts: ImportOptions,
+    audit: Option<&Audit>,
+) -> Result<Action, Error> {
+    validate(&row)?;
+    if !seen.insert(row.isbn.clone()) {
+        return Err(Error::InvalidData(i18n::message("detail.duplicate_isbn")));
+    }
+
+    let existing = query_by_isbn(&row.isbn).await;
+    let action = match &existing {
+        None => Action::Create,
+        Some(_) if !opts.upsert => return Err(Error::BookAlreadyExist),
+        Some(book) => {
+            let borrowed = book.stock.as_u32() - book.remain.as_u32();
+            if borrowed > row.stock.as_u32() {
+                return Err(Error::StockIsntEnough);
+            }
+            Action::Update
+        }
+    };
+
+    if opts.dry_run {
+        return Ok(action);
+    }
+
+    let isbn = row.isbn.clone();
+    let contributors: Vec<Contributor> = row
+        .contributors
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Contributor {
+            isbn: isbn.clone(),
+            name: c.name.clone(),
+            role: c.role,
+            position: i as u32,
+        })
+        .collect();
+    let subjects = row.subjects.clone();
+
+    //每一行单独记录审计日志，新增时 before 为空
+    let before = existing.as_ref().map_or(Value::Null, snapshot);
+    let mut after = snapshot(&row);
+    if let Some(after) = after.as_object_mut() {
+        after.remove("contributors");
+        after.remove("subjects");
+    }
+    if !contributors.is_empty() {
+        after["contributors"] = snapshot(&contributors);
+    }
+    if !subjects.is_empty() {
+        after["subjects"] = snapshot(&subjects);
+    }
+    let target = isbn.clone();
+
+    record_if(
+        audit,
+        audit::Action::BookImport,
+        target.as_str(),
+        diff(&before, &after),
+        async move |tx: &mut Tx| {
+            match action {
+                Action::Create => {
+                    let book = Book {
+                        name: row.name,
+                        author: row.author,
+                        isbn: row.isbn,
+                        press: row.press,
+                        stock: row.stock,
+                        remain: row.stock,
+                        publication_year: row.publication_year,
+                        edition: row.edition,
+                        language: row.language,
+                        page_count: row.page_count,
+                        description: row.description,
+                        call_number: row.call_number,
+                        shelf_location: row.shelf_location,
+                        cover: None,
+                        status: BookStatus::Available,
+                        withdrawn_date: None,
+                        withdrawn_reason: None,
+                    };
+                    add(tx, book, &contributors, &subjects).await
+                }
+                Action::Update => {
+                    let book = UpdateBook {
+                        name: Some(row.name),
+                        author: Some(row.author),
+                        press: Some(row.press),
+                        stock: Some(row.stock),
+                        remain: None,
+                        publication_year: row.publication_year,
+                        edition: row.edition,
+                        language: row.language,
+                        page_count: row.page_count,
+                        description: row.description,
+                        call_number: row.call_number,
+                        shelf_location: row.shelf_location,
+                        cover: None,
+                    };
+                    //文件中没有责任者或主题时保留原有的
+                    let contributors =
+                        (!contributors.is_empty()).then_some(contributors.as_slice());
+                    let subjects = (!subjects.is_empty()).then_some(subjects.as_slice());
+                    update(tx, &row.isbn, book, contributors, subjects).await
+                }
+            }
+        },
+    )
+    .await?;
+
+    Ok(action)
+}
+
+pub async fn export(format: Format) -> Result<Vec<u8>, Error> {
+    //导出完整馆藏，包括已下架的书籍；CSV/JSON 与导入的列一致，不含库存余量、封面和下架状态
+    let books = list(true).await?;
+    let (contributors, subjects) = load_details(&books).await?;
+    let contributors_of = |book: &Book| contributors.get(&book.isbn).map_or(&[][..], Vec::as_slice);
+    let subjects_of = |book: &Book| subjects.get(&book.isbn).map_or(&[][..], Vec::as_slice);
+    match format {
+        Format::Marc | Format::MarcXml => {
+            let records: Vec<Record> = books
+                .iter()
+                .map(|book| marc::to_record(book, contributors_of(book), subjects_of(book)))
+                .collect();
+            marc::write(&records, format)
+        }
+        Format::Csv | Format::Json => {
+            let rows: Vec<BookRow> = books
+                .iter()
+                .map(|book| to_row(book, contributors_of(book), subjects_of(book)))
+                .collect();
+            write_rows(&rows, format)
+        }
+    }
+}
+
+type Details = (HashMap<Isbn, Vec<Contributor>>, HashMap<Isbn, Vec<Subject>>);
+
+async fn load_details(books: &[Book]) -> Result<Details, Error> {
+    let isbns: Vec<Isbn> = books.iter().map(|book| book.isbn.clone()).collect();
+
+    let mut contributors: HashMap<Isbn, Vec<Contributor>> = HashMap::new();
+    for c in list_contributors(&isbns).await? {
+        contributors.entry(c.isbn.clone()).or_default().push(c);
+    }
+    let mut subjects: HashMap<Isbn, Vec<Subject>> = HashMap::new();
+    for s in list_subjects(&isbns).await? {
+        subjects.entry(s.isbn).or_default().push(s.subject);
+    }
+    Ok((contributors, subjects))
+}
+
+fn to_row(book: &Book, contributors: &[Contributor], subjects: &[Subject]) -> BookRow {
+    BookRow {
+        name: book.name.clone(),
+        isbn: book.isbn.clone(),
+        author: book.author.clone(),
+        press: book.press.clone(),
+        stock: book.stock,
+        publication_year: book.publication_year,
+        edition: book.edition.clone(),
+        language: book.language.clone(),
+        page_count: book.page_count,
+        description: book.description.clone(),
+        call_number: book.call_number.clone(),
+        shelf_location: book.shelf_location.clone(),
+        contributors: contributors
+            .iter()
+            .map(|c| ContributorRow {
+                name: c.name.clone(),
+                role: c.role,
+            })
+            .collect(),
+        subjects: subjects.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::run;
+
+    const CSV: &str = "name,isbn,author,press,stock,contributors,subjects
+三体,978-7-5366-9293-0,刘慈欣,重庆出版社,3,刘慈欣; Ken Liu (translator),科幻小说; 长篇小说
+活着,9787506365437,余华,作家出版社,1,,
+";
+
+    #[test]
+    fn parse_list_columns() {
+        let rows: Vec<BookRow> = parse_rows(CSV.as_bytes(), Format::Csv)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let contributors: Vec<_> = rows[0]
+            .contributors
+            .iter()
+            .map(|c| (c.name.as_str(), c.role))
+            .collect();
+        assert_eq!(
+            contributors,
+            [
+                ("刘慈欣", ContributorRole::Author),
+                ("Ken Liu", ContributorRole::Translator)
+            ]
+        );
+        let subjects: Vec<_> = rows[0].subjects.iter().map(Subject::as_str).collect();
+        assert_eq!(subjects, ["科幻小说", "长篇小说"]);
+        assert!(rows[1].contributors.is_empty() && rows[1].subjects.is_empty());
+
+        //括号中不是已知角色时属于姓名
+        let c = parse_contributor("Fitzgerald, F. Scott (Francis Scott)");
+        assert_eq!(c.name.as_str(), "Fitzgerald, F. Scott (Francis Scott)");
+        assert_eq!(c.role, ContributorRole::Author);
+    }
+
+    #[test]
+    fn export_rows_round_trip() {
+        let rows: Vec<BookRow> = parse_rows(CSV.as_bytes(), Format::Csv)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        for format in [Format::Csv, Format::Json] {
+            let data = write_rows(&rows, format).unwrap();
+            let parsed: Vec<BookRow> = parse_rows(&data, format)
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                serde_json::to_value(&rows).unwrap(),
+                "{format:?}"
+            );
+        }
+    }
+
+    fn csv(rows: &[(&str, u32)]) -> Vec<u8> {
+        let mut data = "name,isbn,author,press,stock,subjects\n".to_string();
+        for (isbn, stock) in rows {
+            data += &format!("导入测试,{isbn},作者,出版社,{stock},主题\n");
+        }
+        data.into_bytes()
+    }
+
+    #[test]
+    fn import_books() {
+        run(async {
+            let opts = ImportOptions::default();
+            //文件内重复的 ISBN（含 ISBN-10 形式）只导入第一行
+            let data = csv(&[
+                ("9781402894626", 2),
+                ("9781566199094", 1),
+                ("1-56619-909-3", 1),
+                ("9781566199095", 1),
+            ]);
+            let dry = ImportOptions {
+                dry_run: true,
+                ..opts
+            };
+            let report = import(&data, Format::Csv, dry, None).await.unwrap();
+            assert_eq!((report.created, report.failed), (2, 2));
+            assert!(query_by_isbn(&"9781402894626".parse().unwrap())
+                .await
+                .is_none());
+
+            let report = import(&data, Format::Csv, opts, None).await.unwrap();
+            assert_eq!((report.created, report.updated, report.failed), (2, 0, 2));
+            let rows: Vec<_> = report.errors.iter().map(|e| e.row).collect();
+            assert_eq!(rows, [3, 4]);
+            //校验位错误
+            assert_eq!(report.errors[1].code, 130000);
+            let isbn: Isbn = "9781402894626".parse().unwrap();
+            assert_eq!(query_by_isbn(&isbn).await.unwrap().stock.as_u32(), 2);
+            let subjects = list_subjects(std::slice::from_ref(&isbn)).await.unwrap();
+            assert_eq!(subjects.len(), 1);
+
+            //已存在的书籍默认报错，upsert 时更新
+            let data = csv(&[("9781402894626", 5)]);
+            let report = import(&data, Format::Csv, opts, None).await.unwrap();
+            assert_eq!(report.failed, 1);
+            let upsert = ImportOptions {
+                upsert: true,
+                ..opts
+            };
+            let report = import(&data, Format::Csv, upsert, None).await.unwrap();
+            assert_eq!((report.created, report.updated), (0, 1));
+            let book = query_by_isbn(&isbn).await.unwrap();
+            assert_eq!((book.stock.as_u32(), book.remain.as_u32()), (5, 5));
+        })
+    }
+}
@@ -0,0 +1,535 @@
+//MARC21 书目记录与书籍的对应关系：
+//020 ISBN，245 题名，100/700 责任者，250 版次，260/264 出版者和出版年，300 页数，
+//520 简介，650 主题，041（或 008/35-37）语种，852 索书号和馆藏位置，本地字段 949 $c 册数
+use super::book::{BookRow, ContributorRow};
+use super::Format;
+use crate::db::book::{Book, Contributor};
+use crate::error::Error;
+use crate::i18n;
+use crate::marc::{iso2709, xml, Field, Record};
+use crate::types::{
+    Author, Bookname, CallNumber, ContributorRole, Description, Edition, Isbn, Language, PageCount,
+    Press, PublicationYear, ShelfLocation, Stock, Subject,
+};
+use log::debug;
+
+//记录中没有 949 $c 时按 1 册入库
+const DEFAULT_STOCK: u32 = 1;
+
+pub fn parse(data: &[u8], format: Format) -> Result<Vec<Result<BookRow, String>>, Error> {
+    let records = match format {
+        Format::Marc => iso2709::parse(data),
+        Format::MarcXml => xml::parse(data).map_err(Error::InvalidData)?,
+        Format::Csv | Format::Json => {
+            return Err(Error::InvalidData(i18n::message(
+                "detail.marc_format_required",
+            )))
+        }
+    };
+
+    Ok(records
+        .into_iter()
+        .map(|record| record.and_then(|record| to_row(&record)))
+        .collect())
+}
+
+pub fn write(records: &[Record], format: Format) -> Result<Vec<u8>, Error> {
+    match format {
+        Format::Marc => iso2709::write(records).map_err(Error::InvalidData),
+        Format::MarcXml => xml::write(records).map_err(|e| {
+            debug!("{e}");
+            Error::InternalErr
+        }),
+        Format::Csv | Format::Json => Err(Error::InvalidData(i18n::message(
+            "detail.marc_format_required",
+        ))),
+    }
+}
+
+//去掉编目时附加在末尾的 ISBD 标识符，如 `题名 /`、`出版者,`
+fn clean(s: &str) -> &str {
+    s.trim()
+        .trim_end_matches([' ', '/', ':', ';', ',', '=', '.'])
+        .trim()
+}
+
+fn first_number(s: &str) -> Option<u32> {
+    let digits: String = s
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+fn to_role(field: &Field) -> ContributorRole {
+    let relator = field
+        .subfield('4')
+        .or_else(|| field.subfield('e'))
+        .map(|s| clean(s).to_lowercase())
+        .unwrap_or_default();
+    match relator.as_str() {
+        "trl" | "translator" | "译" | "译者" => ContributorRole::Translator,
+        "edt" | "editor" | "编" | "编者" | "主编" => ContributorRole::Editor,
+        _ => ContributorRole::Author,
+    }
+}
+
+fn role_codes(role: ContributorRole) -> (&'static str, &'static str) {
+    match role {
+        ContributorRole::Author => ("author", "aut"),
+        ContributorRole::Translator => ("translator", "trl"),
+        ContributorRole::Editor => ("editor", "edt"),
+    }
+}
+
+fn to_row(record: &Record) -> Result<BookRow, String> {
+    let isbn = record
+        .field("020")
+        .and_then(|field| field.subfield('a'))
+        .and_then(|s| s.split_whitespace().next())
+        .ok_or_else(|| i18n::message("detail.marc_missing_isbn"))?;
+    let isbn: Isbn = isbn
+        .parse()
+        .map_err(|_| i18n::format("detail.marc_invalid_isbn", &[("isbn", isbn.to_string())]))?;
+
+    let title = record
+        .field("245")
+        .ok_or_else(|| i18n::message("detail.marc_missing_title"))?;
+    let name = match (title.subfield('a'), title.subfield('b')) {
+        (Some(a), Some(b)) => format!("{}: {}", clean(a), clean(b)),
+        (Some(a), None) => clean(a).to_string(),
+        _ => return Err(i18n::message("detail.marc_missing_title")),
+    };
+
+    let contributors: Vec<ContributorRow> = record
+        .fields("100")
+        .chain(record.fields("700"))
+        .filter_map(|field| {
+            field.subfield('a').map(|name| ContributorRow {
+                name: Author::from(clean(name)),
+                role: to_role(field),
+            })
+        })
+        .collect();
+    let author = contributors
+        .iter()
+        .find(|c| c.role == ContributorRole::Author)
+        .or_else(|| contributors.first())
+        .map(|c| c.name.clone())
+        .ok_or_else(|| i18n::message("detail.marc_missing_author"))?;
+
+    //优先取 264 出版说明（第二指示符为 1），其次 260
+    let imprint = record
+        .fields("264")
+        .find(|field| matches!(field, Field::Data { ind2: '1', .. }))
+        .or_else(|| record.field("260"))
+        .or_else(|| record.field("264"));
+    let press = imprint
+        .and_then(|field| field.subfield('b'))
+        .map(clean)
+        .ok_or_else(|| i18n::message("detail.marc_missing_press"))?;
+    let publication_year = imprint
+        .and_then(|field| field.subfield('c'))
+        .and_then(first_number)
+        .or_else(|| {
+            record
+                .control("008")
+                .and_then(|f| f.get(7..11))
+                .and_then(|s| s.parse().ok())
+        })
+        .map(PublicationYear::from);
+
+    let language = record
+        .field("041")
+        .and_then(|field| field.subfield('a'))
+        .map(clean)
+        .or_else(|| {
+            record
+                .control("008")
+                .and_then(|f| f.get(35..38))
+                .filter(|s| s.bytes().all(|b| b.is_ascii_lowercase()) && *s != "und")
+        })
+        .map(Language::from);
+
+    let holding = record.field("852");
+    let call_number = holding
+        .and_then(|field| field.subfield('h'))
+        .or_else(|| record.field("050").and_then(|field| field.subfield('a')))
+        .map(|s| CallNumber::from(clean(s)));
+
+    Ok(BookRow {
+        name: Bookname::from(name.as_str()),
+        isbn,
+        author,
+        press: Press::from(press),
+        stock: Stock::from(
+            record
+                .field("949")
+                .and_then(|field| field.subfield('c'))
+                .and_then(first_number)
+                .unwrap_or(DEFAULT_STOCK),
+        ),
+        publication_year,
+        edition: record
+            .field("250")
+            .and_then(|field| field.subfield('a'))
+            .map(|s| Edition::from(clean(s))),
+        language,
+        page_count: record
+            .field("300")
+            .and_then(|field| field.subfield('a'))
+            .and_then(first_number)
+            .map(PageCount::from),
+        description: record
+            .field("520")
+            .and_then(|field| field.subfield('a'))
+            .map(|s| Description::from(s.trim())),
+        call_number,
+        shelf_location: holding
+            .and_then(|field| field.subfield('c'))
+            .map(|s| ShelfLocation::from(clean(s))),
+        contributors,
+        subjects: record
+            .fields("650")
+            .filter_map(|field| field.subfield('a'))
+            .map(|s| Subject::from(clean(s)))
+            .collect(),
+    })
+}
+
+//008 定长字段：06 日期类型，07-10 出版年，35-37 语种代码
+fn fixed_field(book: &Book) -> String {
+    let (kind, date) = match book.publication_year {
+        Some(year) => ('s', format!("{:04}", year.as_u32())),
+        None => ('n', "uuuu".to_string()),
+    };
+    let language = book
+        .language
+        .as_ref()
+        .map(|l| l.as_str())
+        .filter(|l| l.len() == 3 && l.bytes().all(|b| b.is_ascii_lowercase()))
+        .unwrap_or("und");
+    format!("{:6}{kind}{date}{:24}{language}{:2}", "", "", "")
+}
+
+pub fn to_record(book: &Book, contributors: &[Contributor], subjects: &[Subject]) -> Record {
+    let isbn = book.isbn.as_str();
+    let mut record = Record::new();
+    record.push(Field::Control {
+        tag: "001".into(),
+        value: isbn.into(),
+    });
+    record.push(Field::Control {
+        tag: "008".into(),
+        value: fixed_field(book),
+    });
+    record.push(Field::data("020", ' ', ' ', &[('a', isbn)]));
+    if let Some(language) = &book.language {
+        record.push(Field::data("041", '0', ' ', &[('a', language.as_str())]));
+    }
+
+    let mut names: Vec<(&str, ContributorRole)> = contributors
+        .iter()
+        .map(|c| (c.name.as_str(), c.role))
+        .collect();
+    if names.is_empty() {
+        names.push((book.author.as_str(), ContributorRole::Author));
+    }
+    for (i, (name, role)) in names.into_iter().enumerate() {
+        let (relator, code) = role_codes(role);
+        let tag = if i == 0 { "100" } else { "700" };
+        record.push(Field::data(
+            tag,
+            '1',
+            ' ',
+            &[('a', name), ('e', relator), ('4', code)],
+        ));
+    }
+
+    record.push(Field::data("245", '1', '0', &[('a', book.name.as_str())]));
+    if let Some(edition) = &book.edition {
+        record.push(Field::data("250", ' ', ' ', &[('a', edition.as_str())]));
+    }
+    let year = book.publication_year.map(|y| y.as_u32().to_string());
+    let mut imprint = vec![('b', book.press.as_str())];
+    if let Some(year) = &year {
+        imprint.push(('c', year));
+    }
+    record.push(Field::data("264", ' ', '1', &imprint));
+    if let Some(pages) = book.page_count {
+        let pages = format!("{} p.", pages.as_u32());
+        record.push(Field::data("300", ' ', ' ', &[('a', &pages)]));
+    }
+    if let Some(description) = &book.description {
+        record.push(Field::data("520", ' ', ' ', &[('a', description.as_str())]));
+    }
+    for subject in subjects {
+        record.push(Field::data("650", ' ', '4', &[('a', subject.as_str())]));
+    }
+
+    let mut holding = vec![];
+    if let Some(location) = &book.shelf_location {
+        holding.push(('c', location.as_str()));
+    }
+    if let Some(call_number) = &book.call_number {
+        holding.push(('h', call_number.as_str()));
+    }
+    if !holding.is_empty() {
+        record.push(Field::data("852", ' ', ' ', &holding));
+    }
+    let stock = book.stock.as_u32().to_string();
+    record.push(Field::data("949", ' ', ' ', &[('c', &stock)]));
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BookStatus;
+    use serde_json::json;
+
+    fn book() -> Book {
+        Book {
+            name: Bookname::from("三体"),
+            author: Author::from("刘慈欣"),
+            isbn: "9787536692930".parse().unwrap(),
+            press: Press::from("重庆出版社"),
+            stock: Stock::from(3),
+            remain: Stock::from(3),
+            publication_year: Some(PublicationYear::from(2008)),
+            edition: Some(Edition::from("第1版")),
+            language: Some(Language::from("chi")),
+            page_count: Some(PageCount::from(302)),
+            description: Some(Description::from("地球文明与三体文明的第一次接触")),
+            call_number: Some(CallNumber::from("I247.55/L750")),
+            shelf_location: Some(ShelfLocation::from("A 区 3 架")),
+            cover: None,
+            status: BookStatus::default(),
+            withdrawn_date: None,
+            withdrawn_reason: None,
+        }
+    }
+
+    fn contributors() -> Vec<Contributor> {
+        [
+            ("刘慈欣", ContributorRole::Author),
+            ("Ken Liu", ContributorRole::Translator),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, role))| Contributor {
+            isbn: book().isbn,
+            name: Author::from(name),
+            role,
+            position: i as u32,
+        })
+        .collect()
+    }
+
+    fn round_trip(format: Format, subjects: &[Subject]) -> Result<BookRow, Error> {
+        let record = to_record(&book(), &contributors(), subjects);
+        let data = write(&[record], format)?;
+        let mut rows = parse(&data, format)?;
+        assert_eq!(rows.len(), 1);
+        Ok(rows.remove(0).unwrap())
+    }
+
+    #[test]
+    fn encode_and_decode_book() {
+        let subjects = [Subject::from("科幻小说"), Subject::from("长篇小说")];
+        for format in [Format::Marc, Format::MarcXml] {
+            let row = round_trip(format, &subjects).unwrap();
+            assert_eq!(
+                serde_json::to_value(&row).unwrap(),
+                json!({
+                    "name": "三体",
+                    "isbn": "9787536692930",
+                    "author": "刘慈欣",
+                    "press": "重庆出版社",
+                    "stock": 3,
+                    "publication_year": 2008,
+                    "edition": "第1版",
+                    "language": "chi",
+                    "page_count": 302,
+                    "description": "地球文明与三体文明的第一次接触",
+                    "call_number": "I247.55/L750",
+                    "shelf_location": "A 区 3 架",
+                    "contributors": "刘慈欣 (author); Ken Liu (translator)",
+                    "subjects": "科幻小说; 长篇小说",
+                }),
+                "{format:?}"
+            );
+            let contributors: Vec<_> = row
+                .contributors
+                .iter()
+                .map(|c| (c.name.as_str(), c.role))
+                .collect();
+            assert_eq!(
+                contributors,
+                [
+                    ("刘慈欣", ContributorRole::Author),
+                    ("Ken Liu", ContributorRole::Translator)
+                ]
+            );
+            let subjects: Vec<_> = row.subjects.iter().map(Subject::as_str).collect();
+            assert_eq!(subjects, ["科幻小说", "长篇小说"]);
+        }
+    }
+
+    #[test]
+    fn reject_oversized_record() {
+        //单个字段超过 9999 字节
+        let mut long = book();
+        long.description = Some(Description::from("长".repeat(4000).as_str()));
+        let record = to_record(&long, &contributors(), &[]);
+        let err = write(std::slice::from_ref(&record), Format::Marc).unwrap_err();
+        assert!(matches!(err, Error::InvalidData(e) if e.contains("field 520")));
+        assert!(write(&[record], Format::MarcXml).is_ok());
+
+        //整条记录超过 99999 字节
+        let subjects: Vec<_> = (0..5000)
+            .map(|i| Subject::from(format!("主题{i}").as_str()))
+            .collect();
+        let err = round_trip(Format::Marc, &subjects).unwrap_err();
+        assert!(matches!(err, Error::InvalidData(e) if e.contains("longer than 99999")));
+        assert_eq!(
+            round_trip(Format::MarcXml, &subjects)
+                .unwrap()
+                .subjects
+                .len(),
+            5000
+        );
+    }
+
+    //美国国会图书馆风格的外部记录：ISBN-10 带限定语、ISBD 标点、260 出版说明、
+    //非 ASCII 字符，以及一条缺少 020 的连续出版物记录
+    const LOC_MARC: &[u8] = include_bytes!("../../tests/fixtures/loc.mrc");
+    const LOC_MARCXML: &[u8] = include_bytes!("../../tests/fixtures/loc.xml");
+
+    fn to_book(row: &BookRow) -> (Book, Vec<Contributor>) {
+        let book = Book {
+            name: row.name.clone(),
+            author: row.author.clone(),
+            isbn: row.isbn.clone(),
+            press: row.press.clone(),
+            stock: row.stock,
+            remain: row.stock,
+            publication_year: row.publication_year,
+            edition: row.edition.clone(),
+            language: row.language.clone(),
+            page_count: row.page_count,
+            description: row.description.clone(),
+            call_number: row.call_number.clone(),
+            shelf_location: row.shelf_location.clone(),
+            cover: None,
+            status: BookStatus::default(),
+            withdrawn_date: None,
+            withdrawn_reason: None,
+        };
+        let contributors = row
+            .contributors
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Contributor {
+                isbn: row.isbn.clone(),
+                name: c.name.clone(),
+                role: c.role,
+                position: i as u32,
+            })
+            .collect();
+        (book, contributors)
+    }
+
+    fn names(row: &BookRow) -> Vec<(&str, ContributorRole)> {
+        row.contributors
+            .iter()
+            .map(|c| (c.name.as_str(), c.role))
+            .collect()
+    }
+
+    #[test]
+    fn parse_external_records() {
+        for (data, format) in [(LOC_MARC, Format::Marc), (LOC_MARCXML, Format::MarcXml)] {
+            let rows = parse(data, format).unwrap();
+            assert_eq!(rows.len(), 3, "{format:?}");
+
+            let gatsby = rows[0].as_ref().unwrap();
+            assert_eq!(
+                serde_json::to_value(gatsby).unwrap(),
+                json!({
+                    "name": "The great Gatsby",
+                    "isbn": "9780743273565",
+                    "author": "Fitzgerald, F. Scott",
+                    "press": "Scribner",
+                    "stock": 1,
+                    "publication_year": 2004,
+                    "edition": "1st Scribner trade pbk. ed",
+                    "language": "eng",
+                    "page_count": 180,
+                    "description": null,
+                    "call_number": "PS3511.I9",
+                    "shelf_location": null,
+                    "contributors": "Fitzgerald, F. Scott (author); Bruccoli, Matthew Joseph (editor)",
+                    "subjects": "Rich people; Long Island (N.Y.)",
+                }),
+                "{format:?}"
+            );
+            assert_eq!(
+                names(gatsby),
+                [
+                    ("Fitzgerald, F. Scott", ContributorRole::Author),
+                    ("Bruccoli, Matthew Joseph", ContributorRole::Editor)
+                ]
+            );
+            let subjects: Vec<_> = gatsby.subjects.iter().map(Subject::as_str).collect();
+            assert_eq!(subjects, ["Rich people", "Long Island (N.Y.)"]);
+
+            let cien = rows[1].as_ref().unwrap();
+            assert_eq!(cien.isbn.as_str(), "9788420471839");
+            assert_eq!(cien.name.as_str(), "Cien años de soledad");
+            assert_eq!(cien.author.as_str(), "García Márquez, Gabriel");
+            assert_eq!(cien.press.as_str(), "Real Academia Española");
+            assert_eq!(cien.publication_year.map(|y| y.as_u32()), Some(2007));
+            assert_eq!(cien.language.as_ref().map(Language::as_str), Some("spa"));
+            assert_eq!(cien.page_count.map(|p| p.as_u32()), Some(745));
+
+            let err = rows[2].as_ref().unwrap_err();
+            assert!(err.contains("020"), "{err}");
+        }
+    }
+
+    #[test]
+    fn round_trip_external_records() {
+        let rows: Vec<BookRow> = parse(LOC_MARC, Format::Marc)
+            .unwrap()
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect();
+        assert_eq!(rows.len(), 2);
+        let records: Vec<Record> = rows
+            .iter()
+            .map(|row| {
+                let (book, contributors) = to_book(row);
+                to_record(&book, &contributors, &row.subjects)
+            })
+            .collect();
+
+        for format in [Format::Marc, Format::MarcXml] {
+            let data = write(&records, format).unwrap();
+            let parsed = parse(&data, format).unwrap();
+            assert_eq!(parsed.len(), rows.len());
+            for (row, parsed) in rows.iter().zip(parsed) {
+                let parsed = parsed.unwrap();
+                assert_eq!(
+                    serde_json::to_value(&parsed).unwrap(),
+                    serde_json::to_value(row).unwrap(),
+                    "{format:?}"
+                );
+                assert_eq!(names(&parsed), names(row));
+                assert_eq!(parsed.subjects, row.subjects);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::error::Error;
+use crate::i18n;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+pub mod book;
+pub mod marc;
+pub mod user;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    Json,
+    //MARC21 交换格式（ISO 2709），仅用于书籍
+    Marc,
+    MarcXml,
+}
+
+impl Format {
+    //根据文件扩展名推断格式
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Marc => "application/marc",
+            Format::MarcXml => "application/marcxml+xml",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Marc => "mrc",
+            Format::MarcXml => "xml",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "marc" | "mrc" => Ok(Format::Marc),
+            "marcxml" | "xml" => Ok(Format::MarcXml),
+            _ => Err(Error::InvalidData(i18n::message("detail.unknown_format"))),
+        }
+    }
+}
+
+//导入时单行的错误，row 从 1 开始计数（不含 CSV 表头）
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    pub row: usize,
+    pub key: Option<String>,
+    pub code: u32,
+    pub message: String,
+}
+
+impl RowError {
+    pub fn new(row: usize, key: Option<String>, err: &Error) -> Self {
+        let (code, message) = match err {
+            Error::InvalidData(detail) => (err.code(), detail.clone()),
+            err => (err.code(), err.message()),
+        };
+
+        Self {
+            row,
+            key,
+            code,
+            message,
+        }
+    }
+}
+
+//把 CSV 或 JSON 数组解析为逐行结果，单行解析失败不影响其它行
+pub fn parse_rows<T>(data: &[u8], format: Format) -> Result<Vec<Result<T, String>>, Error>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data);
+            Ok(reader
+                .deserialize::<T>()
+                .map(|r| r.map_err(|e| e.to_string()))
+                .collect())
+        }
+        Format::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_slice(data).map_err(|e| {
+                Error::InvalidData(i18n::format(
+                    "detail.invalid_json",
+                    &[("error", e.to_string())],
+                ))
+            })?;
+            Ok(values
+                .into_iter()
+                .map(|v| serde_json::from_value::<T>(v).map_err(|e| e.to_string()))
+                .collect())
+        }
+        Format::Marc | Format::MarcXml => Err(marc_unsupported()),
+    }
+}
+
+fn marc_unsupported() -> Error {
+    Error::InvalidData(i18n::message("detail.marc_books_only"))
+}
+
+pub fn write_rows<T: Serialize>(rows: &[T], format: Format) -> Result<Vec<u8>, Error> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for row in rows {
+                writer.serialize(row).map_err(|e| {
+                    debug!("{e}");
+                    Error::InternalErr
+                })?;
+            }
+            writer.into_inner().map_err(|e| {
+                debug!("{e}");
+                Error::InternalErr
+            })
+        }
+        Format::Json => serde_json::to_vec_pretty(rows).map_err(|e| {
+            debug!("{e}");
+            Error::InternalErr
+        }),
+        Format::Marc | Format::MarcXml => Err(marc_unsupported()),
+    }
+}
@@ -0,0 +1,302 @@
+use super::{parse_rows, write_rows, Format, RowError};
+use crate::api::validate;
+use crate::audit::{self, diff, record_if, Audit};
+use crate::db::user::{add, list, query, update, UpdateUser, User};
+use crate::error::Error;
+use crate::i18n;
+use crate::types::{Age, Email, Introduction, Password, Role, Sex, Sid, Status, Username};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use validator::Validate;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Validate)]
+pub struct UserRow {
+    #[validate]
+    pub username: Username,
+    #[validate]
+    pub sid: Sid,
+    #[validate]
+    pub email: Email,
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportOptions {
+    //只校验不写入
+    pub dry_run: bool,
+    //邮箱已存在时更新用户名、学号和角色，否则跳过
+    pub update_existing: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Created,
+    Updated,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportRow {
+    pub row: usize,
+    pub email: Option<Email>,
+    pub username: Option<Username>,
+    pub sid: Option<Sid>,
+    pub role: Option<String>,
+    pub action: Action,
+    //仅新建账号时返回初始密码
+    pub password: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<ReportRow>,
+}
+
+pub async fn import(
+    data: &[u8],
+    format: Format,
+    opts: ImportOptions,
+    audit: Option<&Audit>,
+) -> Result<ImportReport, Error> {
+    let rows = parse_rows::<UserRow>(data, format)?;
+    let mut report = ImportReport {
+        dry_run: opts.dry_run,
+        total: rows.len(),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+
+    for (i, row) in rows.into_iter().enumerate() {
+        let n = i + 1;
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                report.push(failed(n, None, &Error::InvalidData(e)));
+                continue;
+            }
+        };
+
+        let result = import_row(&row, &mut seen, opts, audit).await;
+        let role = row.role.clone().filter(|r| !r.is_empty());
+        let report_row = match result {
+            Ok((action, password)) => ReportRow {
+                row: n,
+                email: Some(row.email),
+                username: Some(row.username),
+                sid: Some(row.sid),
+                role,
+                action,
+                password: password.map(|p| p.as_str().to_string()),
+                message: None,
+            },
+            Err(e) => failed(n, Some(row), &e),
+        };
+        report.push(report_row);
+    }
+
+    Ok(report)
+}
+
+impl ImportReport {
+    fn push(&mut self, row: ReportRow) {
+        match row.action {
+            Action::Created => self.created += 1,
+            Action::Updated => self.updated += 1,
+            Action::Skipped => self.skipped += 1,
+            Action::Failed => self.failed += 1,
+        }
+        self.rows.push(row);
+    }
+}
+
+fn failed(n: usize, row: Option<UserRow>, err: &Error) -> ReportRow {
+    let mut report_row = ReportRow {
+        row: n,
+        email: None,
+        username: None,
+        sid: None,
+        role: None,
+        action: Action::Failed,
+        password: None,
+        message: Some(RowError::new(n, None, err).message),
+    };
+    if let Some(row) = row {
+        report_row.email = Some(row.email);
+        report_row.username = Some(row.username);
+        report_row.sid = Some(row.sid);
+        report_row.role = row.role;
+    }
+    report_row
+}
+
+async fn import_row(
+    row: &UserRow,
+    seen: &mut HashSet<Email>,
+    opts: ImportOptions,
+    audit: Option<&Audit>,
+) -> Result<(Action, Option<Password>), Error> {
+    validate(row)?;
+    let role = match row.role.as_deref() {
+        None | Some("") => None,
+        Some(role) => Some(role.parse::<Role>()?),
+    };
+    if !seen.insert(row.email.clone()) {
+        return Err(Error::InvalidData(i18n::message("detail.duplicate_email")));
+    }
+
+    //每一行单独记录审计日志，新增时 before 为空
+    if let Some(old) = query(&row.email).await {
+        if !opts.update_existing {
+            return Ok((Action::Skipped, None));
+        }
+        if !opts.dry_run {
+            let user = UpdateUser {
+                username: Some(row.username.clone()),
+                password: None,
+                sid: Some(row.sid.clone()),
+                introduction: None,
+                age: None,
+                sex: None,
+                role,
+                status: None,
+                avatar: None,
+                must_change_password: None,
+                locale: None,
+            };
+            record_if(
+                audit,
+                audit::Action::UserImport,
+                row.email.as_str(),
+                diff(&old, &user),
+                async |tx| update(tx, &row.email, user).await,
+            )
+            .await?;
+        }
+        return Ok((Action::Updated, None));
+    }
+
+    if opts.dry_run {
+        return Ok((Action::Created, None));
+    }
+
+    let password = Password::generate();
+    let user = User {
+        username: row.username.clone(),
+        password: password.clone(),
+        sid: row.sid.clone(),
+        email: row.email.clone(),
+        introduction: Introduction::from(""),
+        age: Age::from(0),
+        sex: Sex::from("unknown"),
+        role: Role::User,
+        status: Status::Enabled,
+        avatar: None,
+        must_change_password: true,
+        locale: None,
+    };
+    record_if(
+        audit,
+        audit::Action::UserImport,
+        row.email.as_str(),
+        diff(
+            &Value::Null,
+            &json!({ "email": row.email, "role": row.role }),
+        ),
+        async |tx| add(tx, user, role).await.ok_or(Error::FailedToRegister),
+    )
+    .await?;
+
+    Ok((Action::Created, Some(password)))
+}
+
+//导出的文件可以直接再次导入，不包含密码
+pub async fn export(format: Format) -> Result<Vec<u8>, Error> {
+    let rows: Vec<UserRow> = list()
+        .await?
+        .into_iter()
+        .map(|user| UserRow {
+            username: user.username,
+            sid: user.sid,
+            email: user.email,
+            role: Some(user.role.to_string()),
+        })
+        .collect();
+    write_rows(&rows, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::run;
+    use crate::db::user::verify;
+    use Action::{Created, Failed, Skipped, Updated};
+
+    const ROSTER: &str = "username,sid,email,role
+张三,202300000401,zhang@roster.test,
+李四,202300000402,li@roster.test,librarian
+李四,202300000402,li@roster.test,
+王五,202300000403,wang@roster.test,root
+";
+
+    fn actions(report: &ImportReport) -> Vec<Action> {
+        report.rows.iter().map(|r| r.action).collect()
+    }
+
+    #[test]
+    fn import_roster() {
+        run(async {
+            let opts = ImportOptions::default();
+            let dry = ImportOptions {
+                dry_run: true,
+                ..opts
+            };
+            let report = import(ROSTER.as_bytes(), Format::Csv, dry, None)
+                .await
+                .unwrap();
+            assert_eq!(actions(&report), [Created, Created, Failed, Failed]);
+            assert!(report.rows.iter().all(|r| r.password.is_none()));
+            assert!(query(&Email::from("zhang@roster.test")).await.is_none());
+
+            let report = import(ROSTER.as_bytes(), Format::Csv, opts, None)
+                .await
+                .unwrap();
+            assert_eq!(actions(&report), [Created, Created, Failed, Failed]);
+            let email = Email::from("li@roster.test");
+            let password = Password::from(report.rows[1].password.as_deref().unwrap());
+            let user = verify(&email, &password).await.unwrap();
+            assert_eq!(user.role, Role::Librarian);
+            assert!(user.must_change_password);
+
+            //已存在的邮箱默认跳过，update_existing 时更新
+            let data = "username,sid,email,role\n李四四,202300000404,li@roster.test,user\n";
+            let report = import(data.as_bytes(), Format::Csv, opts, None)
+                .await
+                .unwrap();
+            assert_eq!(actions(&report), [Skipped]);
+            let update_existing = ImportOptions {
+                update_existing: true,
+                ..opts
+            };
+            let report = import(data.as_bytes(), Format::Csv, update_existing, None)
+                .await
+                .unwrap();
+            assert_eq!(actions(&report), [Updated]);
+            let user = query(&email).await.unwrap();
+            assert_eq!(user.username.as_str(), "李四四");
+            assert_eq!(user.role, Role::User);
+            //更新不改变密码
+            assert!(verify(&email, &password).await.is_some());
+        })
+    }
+}
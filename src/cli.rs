@@ -0,0 +1,294 @@
+use crate::api::validate;
+use crate::bulk::book::{self, import, ImportOptions};
+use crate::bulk::{user, Format};
+use crate::db::record::now_with_timezone;
+use crate::db::user::{add, exist, unused_sid, update, UpdateUser, User};
+use crate::db::{dump, migration, missing_tables, transaction};
+use crate::error::Error;
+use crate::i18n;
+use crate::types::{Age, Email, Introduction, Password, Role, Sex, Sid, Status, Username};
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+#[derive(Debug, Parser)]
+#[clap(about = "图书管理系统后端")]
+pub struct Cli {
+    /// 配置文件路径，缺省时使用环境变量 LIBRARY_CONFIG 或 ./config.toml
+    #[clap(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 启动 HTTP 服务（默认）
+    Serve,
+    /// 创建缺失的数据表并应用表结构迁移后退出
+    Migrate,
+    /// 创建管理员账号，未指定密码时随机生成，首次登录后须修改密码
+    CreateAdmin {
+        email: String,
+        #[clap(long, default_value = "admin")]
+        username: String,
+        /// 未指定时生成一个未被占用的学号
+        #[clap(long)]
+        sid: Option<String>,
+        #[clap(long)]
+        password: Option<String>,
+    },
+    /// 重置用户密码，未指定密码时随机生成，登录后须修改密码
+    ResetPassword {
+        email: String,
+        #[clap(long)]
+        password: Option<String>,
+        /// 同时启用被停用的账号
+        #[clap(long)]
+        enable: bool,
+    },
+    /// 从 CSV、JSON 或 MARC（ISO 2709 / MARCXML）文件批量导入书籍
+    ImportBooks {
+        file: PathBuf,
+        /// 文件格式，缺省时根据扩展名推断
+        #[clap(long)]
+        format: Option<Format>,
+        /// 只校验不写入
+        #[clap(long)]
+        dry_run: bool,
+        /// ISBN 已存在时更新书籍信息
+        #[clap(long)]
+        upsert: bool,
+    },
+    /// 导出全部书籍或用户
+    Export {
+        /// books 或 users
+        data: ExportData,
+        /// 输出文件，缺省时输出到标准输出
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+        #[clap(long, default_value = "csv")]
+        format: Format,
+    },
+    /// 以 JSON 备份所有数据表
+    Backup {
+        /// 输出文件，缺省时输出到标准输出
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// 检查或生成配置文件
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// 校验配置文件及 LIBRARY_* 环境变量，不启动服务
+    Check,
+    /// 输出示例配置
+    Example,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExportData {
+    Books,
+    Users,
+}
+
+impl FromStr for ExportData {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "books" => Ok(ExportData::Books),
+            "users" => Ok(ExportData::Users),
+            _ => Err(Error::InvalidData(i18n::message(
+                "detail.unknown_export_data",
+            ))),
+        }
+    }
+}
+
+//不需要连接数据库，在初始化之前执行
+pub fn config(command: ConfigCommand) -> CliResult {
+    match command {
+        ConfigCommand::Check => {
+            let config = crate::config::load()?;
+            println!(
+                "config ok: listen on {}, db {}",
+                config.global.listen_addr, config.db.addr
+            );
+        }
+        ConfigCommand::Example => print!("{}", crate::config::example()),
+    }
+    Ok(())
+}
+
+fn resolve_format(file: &Path, format: Option<Format>) -> Result<Format, String> {
+    format
+        .or_else(|| Format::from_path(file))
+        .ok_or_else(|| format!("无法识别 {} 的格式，请使用 --format", file.display()))
+}
+
+fn write_output(output: &Option<PathBuf>, data: &[u8]) -> CliResult {
+    match output {
+        Some(path) => fs::write(path, data)?,
+        None => std::io::stdout().write_all(data)?,
+    }
+    Ok(())
+}
+
+//未指定时生成随机密码并输出，只显示这一次
+fn password_or_generate(password: Option<String>) -> Result<Password, Error> {
+    match password {
+        Some(password) => {
+            let password = Password::from(password.as_str());
+            validate(&password)?;
+            Ok(password)
+        }
+        None => {
+            let password = Password::generate();
+            println!("password: {}", password.as_str());
+            Ok(password)
+        }
+    }
+}
+
+async fn create_admin(
+    email: &str,
+    username: &str,
+    sid: Option<String>,
+    password: Option<String>,
+) -> CliResult {
+    let email = Email::from(email);
+    let username = Username::from(username);
+    validate(&email)?;
+    validate(&username)?;
+    let sid = match sid {
+        Some(sid) => {
+            let sid = Sid::from(sid.as_str());
+            validate(&sid)?;
+            sid
+        }
+        None => unused_sid().await?,
+    };
+    if exist(&email).await.is_some() {
+        return Err(Error::UserAlreadyExist.into());
+    }
+
+    let user = User {
+        username,
+        password: password_or_generate(password)?,
+        sid,
+        email: email.clone(),
+        introduction: Introduction::from(""),
+        age: Age::from(0),
+        sex: Sex::from("unknown"),
+        role: Role::Admin,
+        status: Status::Enabled,
+        avatar: None,
+        must_change_password: true,
+        locale: None,
+    };
+    transaction(async |tx| {
+        add(tx, user, Some(Role::Admin))
+            .await
+            .ok_or(Error::FailedToRegister)
+    })
+    .await?;
+    println!("created admin {}", email.as_str());
+    Ok(())
+}
+
+async fn reset_password(email: &str, password: Option<String>, enable: bool) -> CliResult {
+    let email = Email::from(email);
+    exist(&email).await.ok_or(Error::UserNotExist)?;
+    let user = UpdateUser {
+        password: Some(password_or_generate(password)?),
+        status: enable.then_some(Status::Enabled),
+        must_change_password: Some(true),
+        ..Default::default()
+    };
+    transaction(async |tx| update(tx, &email, user).await).await?;
+    println!("password of {} has been reset", email.as_str());
+    Ok(())
+}
+
+pub async fn run(command: Command) -> CliResult {
+    match command {
+        Command::Serve => unreachable!("serve is handled by the binary"),
+        Command::Config { command } => config(command)?,
+        Command::Migrate => {
+            //连接数据库时已经应用了迁移，这里再检查一遍并列出已应用的版本
+            let missing = missing_tables().await;
+            if !missing.is_empty() {
+                return Err(format!("missing tables: {}", missing.join(", ")).into());
+            }
+            let pending = migration::pending().await?;
+            if !pending.is_empty() {
+                return Err(format!("pending migrations: {pending:?}").into());
+            }
+            for m in migration::applied().await? {
+                println!(
+                    "{:>4}  {}  {}",
+                    m.version,
+                    m.applied_date.unwrap_or_default(),
+                    m.description.unwrap_or_default()
+                );
+            }
+            println!(
+                "database schema is up to date (version {})",
+                migration::latest_version()
+            );
+        }
+        Command::CreateAdmin {
+            email,
+            username,
+            sid,
+            password,
+        } => create_admin(&email, &username, sid, password).await?,
+        Command::ResetPassword {
+            email,
+            password,
+            enable,
+        } => reset_password(&email, password, enable).await?,
+        Command::ImportBooks {
+            file,
+            format,
+            dry_run,
+            upsert,
+        } => {
+            let format = resolve_format(&file, format)?;
+            let data = fs::read(&file)?;
+            let report = import(&data, format, ImportOptions { dry_run, upsert }, None).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::Export {
+            data,
+            output,
+            format,
+        } => {
+            let data = match data {
+                ExportData::Books => book::export(format).await?,
+                ExportData::Users => user::export(format).await?,
+            };
+            write_output(&output, &data)?;
+        }
+        Command::Backup { output } => {
+            let backup = json!({
+                "created_date": now_with_timezone(),
+                "tables": dump().await?,
+            });
+            write_output(&output, &serde_json::to_vec_pretty(&backup)?)?;
+        }
+    }
+
+    Ok(())
+}
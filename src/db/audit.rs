@@ -0,0 +1,174 @@
+use crate::error::Error;
+use crate::types::Email;
+use chrono::NaiveDateTime;
+use log::debug;
+use rbatis::crud::{CRUDMut, Skip, CRUD};
+use rbatis::crud_table;
+use rbatis::executor::ExecutorMut;
+use rbatis::plugin::page::{Page, PageRequest};
+
+use super::{Tx, RB};
+
+//审计日志只允许追加，注销用户时去除个人信息是唯一的例外，见 redact
+#[crud_table(table_name:audit_log)]
+pub struct AuditLog {
+    pub id: Option<i64>,
+    pub actor: Email,
+    pub action: String,
+    pub target: String,
+    //JSON 格式的字段变化：{"字段": {"before": .., "after": ..}}
+    pub diff: Option<String>,
+    pub ip: Option<String>,
+    pub created_date: NaiveDateTime,
+    //修改执行失败时追加一条记录，指向先前写入的记录并附上错误信息
+    pub failure_of: Option<i64>,
+    pub error: Option<String>,
+}
+
+//在修改数据的事务中写入，与修改一起提交或回滚
+pub async fn save(tx: &mut Tx, log: &AuditLog) -> Result<i64, Error> {
+    let result = tx.save(log, &[Skip::Column("id")]).await.map_err(|e| {
+        debug!("{e}");
+        Error::FailedToWriteAuditLog
+    })?;
+    result.last_insert_id.ok_or(Error::FailedToWriteAuditLog)
+}
+
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<Email>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+pub async fn search(
+    filter: &AuditFilter,
+    page_no: u64,
+    page_size: u64,
+) -> Result<Page<AuditLog>, Error> {
+    let w = RB
+        .new_wrapper()
+        .do_if(filter.actor.is_some(), |w| w.eq("actor", &filter.actor))
+        .do_if(filter.action.is_some(), |w| w.eq("action", &filter.action))
+        .do_if(filter.target.is_some(), |w| {
+            w.like("target", &filter.target)
+        })
+        .do_if(filter.from.is_some(), |w| w.ge("created_date", filter.from))
+        .do_if(filter.to.is_some(), |w| w.lt("created_date", filter.to))
+        .order_by(false, &["id"]);
+
+    RB.fetch_page_by_wrapper::<AuditLog>(w, &PageRequest::new(page_no, page_size))
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
+//注销用户时在同一事务中改写其审计日志：邮箱替换为占位邮箱，去掉其操作时的 IP，
+//以该用户为对象的记录中的字段变化可能含有个人信息，一并清空；
+//SQLite 的触发器只放行 audit_redaction 中登记的改写，完成后删除登记
+pub async fn redact(tx: &mut Tx, email: &Email, placeholder: &Email) -> Result<(), Error> {
+    let (email, placeholder) = (email.as_str(), placeholder.as_str());
+    let updates = [
+        (
+            "INSERT INTO audit_redaction (email, placeholder) VALUES (?, ?)",
+            vec![email.into(), placeholder.into()],
+        ),
+        (
+            "UPDATE audit_log SET actor = ?, ip = NULL WHERE actor = ?",
+            vec![placeholder.into(), email.into()],
+        ),
+        (
+            "UPDATE audit_log SET target = ?, diff = NULL WHERE target = ?",
+            vec![placeholder.into(), email.into()],
+        ),
+        (
+            "UPDATE audit_log SET diff = REPLACE(diff, ?, ?) WHERE INSTR(diff, ?) > 0",
+            vec![email.into(), placeholder.into(), email.into()],
+        ),
+        (
+            "DELETE FROM audit_redaction WHERE email = ?",
+            vec![email.into()],
+        ),
+    ];
+
+    for (sql, args) in updates {
+        tx.exec(sql, args).await.map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Action;
+    use crate::db::testing::{audit, audit_logs as logs, run};
+    use crate::db::transaction;
+    use serde_json::json;
+
+    #[test]
+    fn redact_logs() {
+        run(async {
+            let email = Email::from("subject@redact.test");
+            let placeholder = Email::from("deleted-1@anonymized.invalid");
+            let by_user = audit(email.as_str());
+            by_user
+                .record(Action::UserUpdate, "other@redact.test", None, async |_| {
+                    Ok(())
+                })
+                .await
+                .unwrap();
+            let by_staff = audit("staff@redact.test");
+            let changes = Some(json!({ "username": { "before": "a", "after": "b" } }));
+            by_staff
+                .record(
+                    Action::UserUpdate,
+                    email.as_str(),
+                    changes,
+                    async |_| Ok(()),
+                )
+                .await
+                .unwrap();
+            let mentioned = Some(json!({ "holder": email.as_str() }));
+            by_staff
+                .record(
+                    Action::LoanCheckin,
+                    "9780306406157",
+                    mentioned,
+                    async |_| Ok(()),
+                )
+                .await
+                .unwrap();
+
+            //只允许 redact 登记过的改写
+            let result = RB
+                .exec(
+                    "UPDATE audit_log SET ip = NULL WHERE actor = ?",
+                    vec![email.as_str().into()],
+                )
+                .await;
+            assert!(result.is_err());
+
+            transaction(async |tx| redact(tx, &email, &placeholder).await)
+                .await
+                .unwrap();
+            let log = &logs("other@redact.test").await[0];
+            assert_eq!(log.actor, placeholder);
+            assert_eq!(log.ip, None);
+            assert!(logs(email.as_str()).await.is_empty());
+            let log = &logs(placeholder.as_str()).await[0];
+            assert_eq!(
+                (log.ip.as_deref(), log.diff.as_deref()),
+                (Some("10.0.0.1"), None)
+            );
+            let log = &logs("9780306406157").await[0];
+            assert!(!log.diff.as_deref().unwrap().contains(email.as_str()));
+        })
+    }
+}
@@ -1,33 +1,107 @@
+use super::record::{list_holders, now_with_timezone};
 use crate::error::Error;
-use crate::types::{Author, Bookname, Isbn, Press, Stock};
+use crate::types::{
+    Author, BookStatus, Bookname, CallNumber, ContributorRole, Description, Edition, Isbn,
+    Language, PageCount, Press, PublicationYear, ShelfLocation, Stock, Subject, WithdrawnReason,
+};
+use chrono::NaiveDateTime;
 use log::debug;
-use rbatis::crud::{Skip, CRUD};
+use rbatis::crud::{CRUDMut, Skip, CRUD};
 use rbatis::crud_table;
+use rbatis::executor::ExecutorMut;
 use serde::{Deserialize, Serialize};
 
-use super::RB;
+use super::{Tx, RB};
 
 #[crud_table(table_name:book)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Book {
     pub name: Bookname,
+    //第一作者，完整的责任者列表见 book_contributor
     pub author: Author,
     pub isbn: Isbn,
     pub press: Press,
     pub stock: Stock,
     pub remain: Stock,
+    pub publication_year: Option<PublicationYear>,
+    pub edition: Option<Edition>,
+    pub language: Option<Language>,
+    pub page_count: Option<PageCount>,
+    pub description: Option<Description>,
+    pub call_number: Option<CallNumber>,
+    pub shelf_location: Option<ShelfLocation>,
+    //封面文件名，见 upload 模块
+    pub cover: Option<String>,
+    pub status: BookStatus,
+    pub withdrawn_date: Option<NaiveDateTime>,
+    pub withdrawn_reason: Option<WithdrawnReason>,
 }
 
-pub async fn fuzzy_query(
-    book_name: &Option<String>,
-    isbn: &Option<String>,
-    author: &Option<String>,
-) -> Result<Vec<Book>, Error> {
+#[crud_table(table_name:book_contributor)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Contributor {
+    pub isbn: Isbn,
+    pub name: Author,
+    pub role: ContributorRole,
+    pub position: u32,
+}
+
+#[crud_table(table_name:book_subject)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BookSubject {
+    pub isbn: Isbn,
+    pub subject: Subject,
+}
+
+#[derive(Debug, Default)]
+pub struct BookFilter {
+    pub name: Option<String>,
+    pub isbn: Option<String>,
+    //同时匹配第一作者和所有责任者
+    pub author: Option<String>,
+    pub press: Option<String>,
+    pub subject: Option<String>,
+    pub language: Option<String>,
+    pub publication_year: Option<u32>,
+    pub call_number: Option<String>,
+    //默认不包含已下架的书籍
+    pub include_withdrawn: bool,
+}
+
+fn like_arg(s: &Option<String>) -> rbson::Bson {
+    rbson::Bson::String(format!("%{}%", s.as_deref().unwrap_or_default()))
+}
+
+pub async fn fuzzy_query(filter: &BookFilter) -> Result<Vec<Book>, Error> {
     let w = RB
         .new_wrapper()
-        .like("name", book_name)
-        .like("isbn", isbn)
-        .like("author", author);
+        .do_if(filter.name.is_some(), |w| w.like("name", &filter.name))
+        .do_if(filter.isbn.is_some(), |w| w.like("isbn", &filter.isbn))
+        .do_if(filter.author.is_some(), |w| {
+            w.and().push(
+                "(author LIKE ? OR isbn IN (SELECT isbn FROM book_contributor WHERE name LIKE ?))",
+                vec![like_arg(&filter.author), like_arg(&filter.author)],
+            )
+        })
+        .do_if(filter.press.is_some(), |w| w.like("press", &filter.press))
+        .do_if(filter.subject.is_some(), |w| {
+            w.and().push(
+                "isbn IN (SELECT isbn FROM book_subject WHERE subject LIKE ?)",
+                vec![like_arg(&filter.subject)],
+            )
+        })
+        .do_if(filter.language.is_some(), |w| {
+            w.eq("language", &filter.language)
+        })
+        .do_if(filter.publication_year.is_some(), |w| {
+            w.eq("publication_year", filter.publication_year)
+        })
+        .do_if(filter.call_number.is_some(), |w| {
+            w.like_right("call_number", &filter.call_number)
+        })
+        .do_if(!filter.include_withdrawn, |w| {
+            w.eq("status", BookStatus::Available)
+        });
 
     RB.fetch_list_by_wrapper::<Book>(w).await.map_err(|e| {
         debug!("fuzzy_query error: {e}");
@@ -41,35 +115,141 @@ pub async fn query_by_isbn(isbn: &Isbn) -> Option<Book> {
         .ok()?
 }
 
-pub async fn add(metadata: Book) -> Result<(), Error> {
+//书籍和责任者、主题在调用方的事务中写入
+pub async fn add(
+    tx: &mut Tx,
+    metadata: Book,
+    contributors: &[Contributor],
+    subjects: &[Subject],
+) -> Result<(), Error> {
     if query_by_isbn(&metadata.isbn).await.is_some() {
-        Err(Error::BookAlreadyExist)
-    } else {
-        RB.save(&metadata, &[])
-            .await
-            .map(|_r| ())
-            .map_err(|_e| Error::FailedToAddBook)
+        return Err(Error::BookAlreadyExist);
+    }
+
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::FailedToAddBook
+    };
+    tx.save(&metadata, &[]).await.map_err(map_err)?;
+    set_contributors(tx, &metadata.isbn, contributors)
+        .await
+        .map_err(map_err)?;
+    set_subjects(tx, &metadata.isbn, subjects)
+        .await
+        .map_err(map_err)
+}
+
+//下架书籍，保留借阅历史；有未归还的借阅时拒绝，全部检查通过后才修改
+pub async fn withdraw(
+    tx: &mut Tx,
+    isbns: &[Isbn],
+    reason: &Option<WithdrawnReason>,
+) -> Result<(), Error> {
+    for isbn in isbns {
+        let book = query_by_isbn(isbn).await.ok_or(Error::BookNotExist)?;
+        if book.status == BookStatus::Withdrawn {
+            return Err(Error::BookWasWithdrawn);
+        }
+        if !list_holders(isbn).await?.is_empty() {
+            return Err(Error::BookIsOnLoan);
+        }
     }
+
+    //检查之后可能有人借出，更新时再以 NOT EXISTS 确认，任何一本未更新则由调用方整体回滚
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::FailedToDeleteBook
+    };
+    let sql = "UPDATE book SET status = ?, withdrawn_date = ?, withdrawn_reason = ?
+        WHERE isbn = ? AND status <> ?
+        AND NOT EXISTS (SELECT 1 FROM borrowed_book WHERE borrowed_book.isbn = book.isbn)";
+    let withdrawn = rbson::Bson::Int32(BookStatus::Withdrawn as i32);
+    let now = rbson::to_bson(&now_with_timezone()).map_err(|e| map_err(e.into()))?;
+    let reason = rbson::to_bson(reason).map_err(|e| map_err(e.into()))?;
+
+    for isbn in isbns {
+        let args = vec![
+            withdrawn.clone(),
+            now.clone(),
+            reason.clone(),
+            isbn.as_str().into(),
+            withdrawn.clone(),
+        ];
+        let result = tx.exec(sql, args).await.map_err(map_err)?;
+        if result.rows_affected != 1 {
+            return Err(Error::BookIsOnLoan);
+        }
+    }
+    Ok(())
 }
 
-pub async fn delete(isbn: &Isbn) -> Result<(), Error> {
-    if query_by_isbn(isbn).await.is_none() {
-        Err(Error::BookNotExist)
-    } else {
-        RB.remove_by_column::<Book, _>("isbn", isbn)
-            .await
-            .map(|_r| ())
-            .map_err(|_e| Error::FailedToDeleteBook)
+pub async fn restore(tx: &mut Tx, isbns: &[Isbn]) -> Result<(), Error> {
+    for isbn in isbns {
+        let book = query_by_isbn(isbn).await.ok_or(Error::BookNotExist)?;
+        if book.status != BookStatus::Withdrawn {
+            return Err(Error::BookIsNotWithdrawn);
+        }
     }
+
+    //检查之后可能已被其他请求恢复，更新时再次确认状态，任何一本未更新则由调用方整体回滚
+    let sql = "UPDATE book SET status = ?, withdrawn_date = NULL, withdrawn_reason = NULL
+        WHERE isbn = ? AND status = ?";
+    for isbn in isbns {
+        let args = vec![
+            rbson::Bson::Int32(BookStatus::Available as i32),
+            isbn.as_str().into(),
+            rbson::Bson::Int32(BookStatus::Withdrawn as i32),
+        ];
+        let result = tx.exec(sql, args).await.map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })?;
+        if result.rows_affected != 1 {
+            return Err(Error::BookIsNotWithdrawn);
+        }
+    }
+    Ok(())
 }
 
-pub async fn list() -> Result<Vec<Book>, Error> {
-    RB.fetch_list::<Book>().await.map_err(|e| {
+pub async fn list(include_withdrawn: bool) -> Result<Vec<Book>, Error> {
+    let w = RB.new_wrapper().do_if(!include_withdrawn, |w| {
+        w.eq("status", BookStatus::Available)
+    });
+    RB.fetch_list_by_wrapper::<Book>(w).await.map_err(|e| {
         debug!("{e:?}");
         Error::BookListWasEmpty
     })
 }
 
+#[derive(Debug)]
+pub struct Holdings {
+    //在架（未下架）的书籍种数和册数
+    pub titles: u64,
+    pub copies: u64,
+}
+
+pub async fn holdings() -> Result<Holdings, Error> {
+    let db_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
+
+    let w = RB.new_wrapper().eq("status", BookStatus::Available);
+    let titles = RB.fetch_count_by_wrapper::<Book>(w).await.map_err(db_err)?;
+    let copies: i64 = RB
+        .fetch(
+            "SELECT CAST(COALESCE(SUM(stock), 0) AS SIGNED) FROM book WHERE status = ?",
+            vec![rbson::Bson::Int32(BookStatus::Available as i32)],
+        )
+        .await
+        .map_err(db_err)?;
+
+    Ok(Holdings {
+        titles,
+        copies: copies.max(0) as u64,
+    })
+}
+
 #[crud_table(table_name:book)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BorrowBook {
@@ -77,12 +257,13 @@ pub struct BorrowBook {
     pub remain: u32,
 }
 
-//检查图书剩余量是否大于零，借阅的书籍是否存在
+//检查图书剩余量是否大于零，借阅的书籍是否存在且未下架
 pub async fn verify_borrow(isbns: &[Isbn]) -> Result<(), Error> {
     let w = RB
         .new_wrapper_table::<BorrowBook>()
         .in_array("isbn", isbns)
-        .gt("remain", 0);
+        .gt("remain", 0)
+        .eq("status", BookStatus::Available);
     RB.fetch_count_by_wrapper::<BorrowBook>(w)
         .await
         .map_err(|e| {
@@ -95,16 +276,50 @@ pub async fn verify_borrow(isbns: &[Isbn]) -> Result<(), Error> {
 }
 
 #[crud_table(table_name:book)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct UpdateBook {
     pub name: Option<Bookname>,
     pub author: Option<Author>,
     pub press: Option<Press>,
     pub stock: Option<Stock>,
     pub remain: Option<Stock>,
+    pub publication_year: Option<PublicationYear>,
+    pub edition: Option<Edition>,
+    pub language: Option<Language>,
+    pub page_count: Option<PageCount>,
+    pub description: Option<Description>,
+    pub call_number: Option<CallNumber>,
+    pub shelf_location: Option<ShelfLocation>,
+    //封面文件名，见 upload 模块
+    pub cover: Option<String>,
 }
 
-pub async fn update(isbn: &Isbn, mut book: UpdateBook) -> Result<(), Error> {
+impl UpdateBook {
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.author.is_none()
+            && self.press.is_none()
+            && self.stock.is_none()
+            && self.remain.is_none()
+            && self.publication_year.is_none()
+            && self.edition.is_none()
+            && self.language.is_none()
+            && self.page_count.is_none()
+            && self.description.is_none()
+            && self.call_number.is_none()
+            && self.shelf_location.is_none()
+            && self.cover.is_none()
+    }
+}
+
+//contributors、subjects 为 None 时保持不变，与书籍信息在调用方的事务中修改
+pub async fn update(
+    tx: &mut Tx,
+    isbn: &Isbn,
+    mut book: UpdateBook,
+    contributors: Option<&[Contributor]>,
+    subjects: Option<&[Subject]>,
+) -> Result<(), Error> {
     if let Some(stock) = book.stock {
         let rawbook = query_by_isbn(isbn).await.ok_or(Error::DbError)?;
         let borrowed = rawbook.stock.as_u32() - rawbook.remain.as_u32();
@@ -114,12 +329,138 @@ pub async fn update(isbn: &Isbn, mut book: UpdateBook) -> Result<(), Error> {
         book.remain = Some(Stock::from(stock.as_u32() - borrowed));
     }
 
-    let w = RB.new_wrapper().eq("isbn", isbn);
-    RB.update_by_wrapper(&book, w, &[Skip::Value(rbatis::Value::Null)])
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
+    if !book.is_empty() {
+        let w = RB.new_wrapper().eq("isbn", isbn);
+        tx.update_by_wrapper(&book, w, &[Skip::Value(rbatis::Value::Null)])
+            .await
+            .map_err(map_err)?;
+    }
+    if let Some(contributors) = contributors {
+        set_contributors(tx, isbn, contributors)
+            .await
+            .map_err(map_err)?;
+    }
+    if let Some(subjects) = subjects {
+        set_subjects(tx, isbn, subjects).await.map_err(map_err)?;
+    }
+    Ok(())
+}
+
+async fn set_contributors(
+    tx: &mut Tx,
+    isbn: &Isbn,
+    contributors: &[Contributor],
+) -> Result<(), rbatis::Error> {
+    tx.remove_by_column::<Contributor, _>("isbn", isbn).await?;
+    if !contributors.is_empty() {
+        tx.save_batch(contributors, &[]).await?;
+    }
+    Ok(())
+}
+
+async fn set_subjects(tx: &mut Tx, isbn: &Isbn, subjects: &[Subject]) -> Result<(), rbatis::Error> {
+    tx.remove_by_column::<BookSubject, _>("isbn", isbn).await?;
+    if subjects.is_empty() {
+        return Ok(());
+    }
+    let records: Vec<BookSubject> = subjects
+        .iter()
+        .map(|subject| BookSubject {
+            isbn: isbn.clone(),
+            subject: subject.clone(),
+        })
+        .collect();
+    tx.save_batch(&records, &[]).await.map(|_| ())
+}
+
+pub async fn list_contributors(isbns: &[Isbn]) -> Result<Vec<Contributor>, Error> {
+    if isbns.is_empty() {
+        return Ok(vec![]);
+    }
+    let w = RB
+        .new_wrapper()
+        .in_array("isbn", isbns)
+        .order_by(true, &["position"]);
+    RB.fetch_list_by_wrapper::<Contributor>(w)
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
+pub async fn list_subjects(isbns: &[Isbn]) -> Result<Vec<BookSubject>, Error> {
+    if isbns.is_empty() {
+        return Ok(vec![]);
+    }
+    RB.fetch_list_by_column::<BookSubject, _>("isbn", isbns)
         .await
         .map_err(|e| {
             debug!("{e}");
             Error::DbError
         })
-        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::record::borrow;
+    use crate::db::testing::{insert_book, insert_user, run};
+    use crate::db::transaction;
+    use crate::types::{Email, Role};
+
+    #[test]
+    fn withdraw_and_restore() {
+        run(async {
+            let isbn: Isbn = "9784101092058".parse().unwrap();
+            let on_loan: Isbn = "9787040123456".parse().unwrap();
+            insert_book(isbn.as_str(), 1).await;
+            insert_book(on_loan.as_str(), 1).await;
+            let email = Email::from("reader@withdraw.test");
+            insert_user(email.as_str(), "202300000501", Role::User).await;
+            transaction(async |tx| borrow(tx, &email, std::slice::from_ref(&on_loan), None).await)
+                .await
+                .unwrap();
+
+            //有未归还的借阅时整批失败
+            let both = [isbn.clone(), on_loan.clone()];
+            let err = transaction(async |tx| withdraw(tx, &both, &None).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::BookIsOnLoan), "{err:?}");
+            assert_eq!(
+                query_by_isbn(&isbn).await.unwrap().status,
+                BookStatus::Available
+            );
+
+            let isbns = [isbn.clone()];
+            let reason = Some(WithdrawnReason::from("破损"));
+            transaction(async |tx| withdraw(tx, &isbns, &reason).await)
+                .await
+                .unwrap();
+            let book = query_by_isbn(&isbn).await.unwrap();
+            assert_eq!(book.status, BookStatus::Withdrawn);
+            assert!(book.withdrawn_date.is_some());
+            assert!(!list(false).await.unwrap().iter().any(|b| b.isbn == isbn));
+            assert!(list(true).await.unwrap().iter().any(|b| b.isbn == isbn));
+            //下架的书籍不能借阅
+            let err = verify_borrow(&isbns).await.unwrap_err();
+            assert!(matches!(err, Error::NoRemainBook), "{err:?}");
+
+            transaction(async |tx| restore(tx, &isbns).await)
+                .await
+                .unwrap();
+            let book = query_by_isbn(&isbn).await.unwrap();
+            assert_eq!(book.status, BookStatus::Available);
+            assert!(book.withdrawn_date.is_none() && book.withdrawn_reason.is_none());
+            let err = transaction(async |tx| restore(tx, &isbns).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::BookIsNotWithdrawn), "{err:?}");
+        })
+    }
 }
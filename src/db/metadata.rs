@@ -0,0 +1,35 @@
+use crate::error::Error;
+use crate::types::Isbn;
+use chrono::NaiveDateTime;
+use log::debug;
+use rbatis::crud::CRUD;
+use rbatis::crud_table;
+
+use super::RB;
+
+//ISBN 书目信息查询结果的缓存，data 为空表示数据源中没有该 ISBN
+#[crud_table(table_name:book_metadata_cache)]
+pub struct MetadataCache {
+    pub isbn: Isbn,
+    pub data: Option<String>,
+    pub fetched_date: NaiveDateTime,
+}
+
+pub async fn query(isbn: &Isbn) -> Option<MetadataCache> {
+    RB.fetch_by_column::<Option<MetadataCache>, _>("isbn", isbn)
+        .await
+        .ok()?
+}
+
+pub async fn save(cache: &MetadataCache) -> Result<(), Error> {
+    RB.remove_by_column::<MetadataCache, _>("isbn", &cache.isbn)
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })?;
+    RB.save(cache, &[]).await.map(|_| ()).map_err(|e| {
+        debug!("{e}");
+        Error::DbError
+    })
+}
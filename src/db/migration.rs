@@ -0,0 +1,534 @@
+//表结构迁移：新数据库按最新的建表语句创建，已有的数据库按版本补齐后来增加的列、索引和外键调整；
+//每一步都先检查当前结构，中途失败后重新执行是安全的
+use super::record::now_with_timezone;
+use super::{Tx, RB, SQLITE_TABLE_BORROWED_BOOK, SQLITE_TABLE_RETURN_BOOK};
+use crate::types::Isbn;
+use log::{info, warn};
+use rbatis::executor::ExecutorMut;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+
+pub const SCHEMA_MIGRATIONS: &str = "CREATE TABLE IF NOT EXISTS `schema_migrations`(
+    `version` BIGINT NOT NULL,
+    `description` VARCHAR(255),
+    `applied_date` DATETIME,
+    PRIMARY KEY ( `version` )
+)";
+
+enum Step {
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+    AddIndex {
+        table: &'static str,
+        name: &'static str,
+        columns: &'static str,
+    },
+    //借阅记录需要保留，去掉借阅表外键上的 ON DELETE CASCADE
+    DropCascade {
+        table: &'static str,
+    },
+    //把早期保存的 ISBN-10 或带连字符的 ISBN 改为 ISBN-13 纯数字形式
+    NormalizeIsbn,
+    //删除 SQLite 触发器，迁移完成后由 init_db 按新的定义重新创建
+    DropTrigger {
+        name: &'static str,
+    },
+}
+
+use Step::*;
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    steps: &'static [Step],
+}
+
+pub const MIGRATIONS: [Migration; 11] = [
+    Migration {
+        version: 1,
+        description: "add user search indexes",
+        steps: &[
+            AddIndex {
+                table: "user",
+                name: "idx_user_username",
+                columns: "`username`",
+            },
+            AddIndex {
+                table: "user",
+                name: "idx_user_sid",
+                columns: "`sid`",
+            },
+            AddIndex {
+                table: "user",
+                name: "idx_user_role_status",
+                columns: "`role`, `status`",
+            },
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "add loan operators",
+        steps: &[
+            AddColumn {
+                table: "borrowed_book",
+                column: "borrow_operator",
+                definition: "VARCHAR(255)",
+            },
+            AddColumn {
+                table: "return_book",
+                column: "borrow_operator",
+                definition: "VARCHAR(255)",
+            },
+            AddColumn {
+                table: "return_book",
+                column: "return_operator",
+                definition: "VARCHAR(255)",
+            },
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "add bibliographic fields to book",
+        steps: &[
+            AddColumn {
+                table: "book",
+                column: "publication_year",
+                definition: "INT",
+            },
+            AddColumn {
+                table: "book",
+                column: "edition",
+                definition: "VARCHAR(255)",
+            },
+            AddColumn {
+                table: "book",
+                column: "language",
+                definition: "VARCHAR(255)",
+            },
+            AddColumn {
+                table: "book",
+                column: "page_count",
+                definition: "INT",
+            },
+            AddColumn {
+                table: "book",
+                column: "description",
+                definition: "TEXT",
+            },
+            AddColumn {
+                table: "book",
+                column: "call_number",
+                definition: "VARCHAR(255)",
+            },
+            AddColumn {
+                table: "book",
+                column: "shelf_location",
+                definition: "VARCHAR(255)",
+            },
+            AddIndex {
+                table: "book",
+                name: "idx_book_call_number",
+                columns: "`call_number`",
+            },
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "add book cover and user avatar",
+        steps: &[
+            AddColumn {
+                table: "book",
+                column: "cover",
+                definition: "VARCHAR(255)",
+            },
+            AddColumn {
+                table: "user",
+                column: "avatar",
+                definition: "VARCHAR(255)",
+            },
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "add book status for withdrawal",
+        steps: &[
+            AddColumn {
+                table: "book",
+                column: "status",
+                definition: "TINYINT DEFAULT 0",
+            },
+            AddColumn {
+                table: "book",
+                column: "withdrawn_date",
+                definition: "DATETIME",
+            },
+            AddColumn {
+                table: "book",
+                column: "withdrawn_reason",
+                definition: "VARCHAR(255)",
+            },
+            AddIndex {
+                table: "book",
+                name: "idx_book_status",
+                columns: "`status`",
+            },
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "keep loan history when books or users are removed",
+        steps: &[
+            DropCascade {
+                table: "borrowed_book",
+            },
+            DropCascade {
+                table: "return_book",
+            },
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "add forced password change",
+        steps: &[AddColumn {
+            table: "user",
+            column: "must_change_password",
+            definition: "TINYINT DEFAULT 0",
+        }],
+    },
+    Migration {
+        version: 8,
+        description: "add user locale",
+        steps: &[AddColumn {
+            table: "user",
+            column: "locale",
+            definition: "VARCHAR(16)",
+        }],
+    },
+    Migration {
+        version: 9,
+        description: "normalize stored isbn to isbn-13",
+        steps: &[NormalizeIsbn],
+    },
+    Migration {
+        version: 10,
+        description: "allow redacting audit log of anonymized users",
+        steps: &[DropTrigger {
+            name: "audit_log_no_update",
+        }],
+    },
+    Migration {
+        version: 11,
+        description: "only allow redacting registered anonymizations in audit log",
+        steps: &[DropTrigger {
+            name: "audit_log_no_update",
+        }],
+    },
+];
+
+//保存 ISBN 的表，book 之外的表都以外键引用 book
+const ISBN_TABLES: [&str; 6] = [
+    "book",
+    "book_contributor",
+    "book_subject",
+    "book_metadata_cache",
+    "borrowed_book",
+    "return_book",
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: Option<String>,
+    pub applied_date: Option<String>,
+}
+
+pub async fn applied() -> Result<Vec<AppliedMigration>, rbatis::Error> {
+    RB.fetch(
+        "SELECT `version`, `description`, CAST(`applied_date` AS CHAR) AS `applied_date`
+        FROM `schema_migrations` ORDER BY `version`",
+        vec![],
+    )
+    .await
+}
+
+//尚未应用的迁移版本，readyz 据此判断表结构是否最新
+pub async fn pending() -> Result<Vec<i64>, rbatis::Error> {
+    let applied: Vec<i64> = applied().await?.iter().map(|m| m.version).collect();
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| m.version)
+        .filter(|v| !applied.contains(v))
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+struct Name {
+    name: String,
+}
+
+async fn columns(table: &str, sqlite: bool) -> Result<Vec<String>, rbatis::Error> {
+    let names: Vec<Name> = if sqlite {
+        let sql = "SELECT `name` FROM pragma_table_info(?)";
+        RB.fetch(sql, vec![table.into()]).await?
+    } else {
+        let sql = "SELECT `COLUMN_NAME` AS `name` FROM information_schema.COLUMNS
+            WHERE `TABLE_SCHEMA` = DATABASE() AND `TABLE_NAME` = ?";
+        RB.fetch(sql, vec![table.into()]).await?
+    };
+    Ok(names.into_iter().map(|n| n.name).collect())
+}
+
+async fn add_index(
+    table: &str,
+    name: &str,
+    columns: &str,
+    sqlite: bool,
+) -> Result<(), rbatis::Error> {
+    if sqlite {
+        let sql = format!("CREATE INDEX IF NOT EXISTS `{name}` ON `{table}` ( {columns} )");
+        RB.exec(&sql, vec![]).await?;
+        return Ok(());
+    }
+    let sql = "SELECT `INDEX_NAME` AS `name` FROM information_schema.STATISTICS
+        WHERE `TABLE_SCHEMA` = DATABASE() AND `TABLE_NAME` = ? AND `INDEX_NAME` = ?";
+    let existing: Vec<Name> = RB.fetch(sql, vec![table.into(), name.into()]).await?;
+    if existing.is_empty() {
+        let sql = format!("CREATE INDEX `{name}` ON `{table}` ( {columns} )");
+        RB.exec(&sql, vec![]).await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct ForeignKey {
+    name: String,
+    column: String,
+    referenced_table: String,
+    referenced_column: String,
+}
+
+//MySQL 可以直接替换外键
+async fn drop_cascade_mysql(table: &str) -> Result<(), rbatis::Error> {
+    let sql = "SELECT k.`CONSTRAINT_NAME` AS `name`, k.`COLUMN_NAME` AS `column`,
+            k.`REFERENCED_TABLE_NAME` AS `referenced_table`,
+            k.`REFERENCED_COLUMN_NAME` AS `referenced_column`
+        FROM information_schema.REFERENTIAL_CONSTRAINTS r
+        JOIN information_schema.KEY_COLUMN_USAGE k
+            ON k.`CONSTRAINT_SCHEMA` = r.`CONSTRAINT_SCHEMA`
+            AND k.`CONSTRAINT_NAME` = r.`CONSTRAINT_NAME`
+        WHERE r.`CONSTRAINT_SCHEMA` = DATABASE() AND r.`TABLE_NAME` = ?
+            AND r.`DELETE_RULE` = 'CASCADE'";
+    let keys: Vec<ForeignKey> = RB.fetch(sql, vec![table.into()]).await?;
+    for key in keys {
+        let sql = format!("ALTER TABLE `{table}` DROP FOREIGN KEY `{}`", key.name);
+        RB.exec(&sql, vec![]).await?;
+        let sql = format!(
+            "ALTER TABLE `{table}` ADD CONSTRAINT `{}` FOREIGN KEY (`{}`) REFERENCES `{}`(`{}`)",
+            key.name, key.column, key.referenced_table, key.referenced_column
+        );
+        RB.exec(&sql, vec![]).await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct Sql {
+    sql: String,
+}
+
+//SQLite 不能修改外键，按当前的建表语句重建表并复制数据
+async fn drop_cascade_sqlite(table: &str) -> Result<(), rbatis::Error> {
+    let sql = "SELECT `sql` FROM `sqlite_master` WHERE `type` = 'table' AND `name` = ?";
+    let current: Option<Sql> = RB.fetch(sql, vec![table.into()]).await?;
+    if !current.is_some_and(|c| c.sql.contains("CASCADE")) {
+        return Ok(());
+    }
+    let create = match table {
+        "borrowed_book" => SQLITE_TABLE_BORROWED_BOOK,
+        "return_book" => SQLITE_TABLE_RETURN_BOOK,
+        _ => return Err(rbatis::Error::from(format!("no schema for table {table}"))),
+    };
+    let rebuilt = format!("{table}_rebuilt");
+    let columns = columns(table, true)
+        .await?
+        .iter()
+        .map(|c| format!("`{c}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut tx = Tx::begin().await?;
+    tx.exec(&format!("DROP TABLE IF EXISTS `{rebuilt}`"), vec![])
+        .await?;
+    tx.exec(
+        &create.replacen(&format!("`{table}`"), &format!("`{rebuilt}`"), 1),
+        vec![],
+    )
+    .await?;
+    let copy = format!("INSERT INTO `{rebuilt}` ({columns}) SELECT {columns} FROM `{table}`");
+    tx.exec(&copy, vec![]).await?;
+    tx.exec(&format!("DROP TABLE `{table}`"), vec![]).await?;
+    tx.exec(
+        &format!("ALTER TABLE `{rebuilt}` RENAME TO `{table}`"),
+        vec![],
+    )
+    .await?;
+    tx.commit().await
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredIsbn {
+    isbn: Option<String>,
+}
+
+async fn stored_isbn(table: &str) -> Result<BTreeSet<String>, rbatis::Error> {
+    let sql = format!("SELECT DISTINCT `isbn` FROM `{table}`");
+    let rows: Vec<StoredIsbn> = RB.fetch(&sql, vec![]).await?;
+    Ok(rows.into_iter().filter_map(|r| r.isbn).collect())
+}
+
+//校验位错误的 ISBN 无法换算，保留原值并在日志中列出，读取时 Isbn 会原样保留这些值
+async fn normalize_isbn(sqlite: bool) -> Result<(), rbatis::Error> {
+    let mut stored = BTreeSet::new();
+    for table in ISBN_TABLES {
+        stored.extend(stored_isbn(table).await?);
+    }
+
+    let mut invalid = vec![];
+    let mut renames = vec![];
+    for old in stored {
+        match old.parse::<Isbn>() {
+            Ok(isbn) if isbn.as_str() != old => renames.push((old, isbn)),
+            Ok(_) => {}
+            Err(_) => invalid.push(old),
+        }
+    }
+    if !invalid.is_empty() {
+        warn!(
+            "isbn with invalid check digit kept as is: {}",
+            invalid.join(", ")
+        );
+    }
+
+    //新旧形式的书籍同时存在时两者的库存和借阅记录无法合并，跳过这些 ISBN，由管理员手动处理
+    let mut books = stored_isbn("book").await?;
+    let mut collisions = vec![];
+    renames.retain(|(old, isbn)| {
+        if books.contains(isbn.as_str()) {
+            collisions.push(format!("{old} -> {}", isbn.as_str()));
+            return false;
+        }
+        books.insert(isbn.as_str().to_string());
+        true
+    });
+    if !collisions.is_empty() {
+        warn!(
+            "isbn kept as is because the normalized book already exists: {}",
+            collisions.join(", ")
+        );
+    }
+    if renames.is_empty() {
+        return Ok(());
+    }
+
+    //先改 book 再改引用它的表，事务内暂缓外键检查
+    let mut tx = Tx::begin().await?;
+    if sqlite {
+        tx.exec("PRAGMA defer_foreign_keys = ON", vec![]).await?;
+    } else {
+        tx.exec("SET FOREIGN_KEY_CHECKS = 0", vec![]).await?;
+    }
+    let mut result = rename_isbn(&mut tx, &renames).await;
+    //MySQL 的会话变量会随连接回到连接池，无论成功与否都要恢复
+    if !sqlite {
+        let reset = tx.exec("SET FOREIGN_KEY_CHECKS = 1", vec![]).await;
+        result = result.and(reset.map(|_| ()));
+    }
+    result?;
+    tx.commit().await?;
+    info!("normalized {} stored isbn", renames.len());
+    Ok(())
+}
+
+async fn rename_isbn(tx: &mut Tx, renames: &[(String, Isbn)]) -> Result<(), rbatis::Error> {
+    for (old, isbn) in renames {
+        for table in ISBN_TABLES {
+            //书目缓存以 ISBN 为主键，新形式可能已有缓存，旧形式的直接删除，之后按需重新查询
+            if table == "book_metadata_cache" {
+                let sql = format!("DELETE FROM `{table}` WHERE `isbn` = ?");
+                tx.exec(&sql, vec![old.as_str().into()]).await?;
+                continue;
+            }
+            let sql = format!("UPDATE `{table}` SET `isbn` = ? WHERE `isbn` = ?");
+            tx.exec(&sql, vec![isbn.as_str().into(), old.as_str().into()])
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn apply(step: &Step, sqlite: bool) -> Result<(), rbatis::Error> {
+    match step {
+        AddColumn {
+            table,
+            column,
+            definition,
+        } => {
+            if !columns(table, sqlite).await?.iter().any(|c| c == column) {
+                let sql = format!("ALTER TABLE `{table}` ADD COLUMN `{column}` {definition}");
+                RB.exec(&sql, vec![]).await?;
+            }
+            Ok(())
+        }
+        AddIndex {
+            table,
+            name,
+            columns,
+        } => add_index(table, name, columns, sqlite).await,
+        DropCascade { table } if sqlite => drop_cascade_sqlite(table).await,
+        DropCascade { table } => drop_cascade_mysql(table).await,
+        NormalizeIsbn => normalize_isbn(sqlite).await,
+        DropTrigger { name } if sqlite => {
+            let sql = format!("DROP TRIGGER IF EXISTS `{name}`");
+            RB.exec(&sql, vec![]).await?;
+            Ok(())
+        }
+        DropTrigger { .. } => Ok(()),
+    }
+}
+
+//依次应用尚未应用的迁移，返回本次应用的迁移
+pub async fn migrate(sqlite: bool) -> Result<Vec<&'static Migration>, rbatis::Error> {
+    RB.exec(SCHEMA_MIGRATIONS, vec![]).await?;
+    let pending = pending().await?;
+    let mut migrated = vec![];
+    for migration in MIGRATIONS.iter().filter(|m| pending.contains(&m.version)) {
+        for step in migration.steps {
+            apply(step, sqlite).await?;
+        }
+        RB.exec(
+            "INSERT INTO `schema_migrations` (`version`, `description`, `applied_date`) VALUES (?, ?, ?)",
+            vec![
+                migration.version.into(),
+                migration.description.into(),
+                now_with_timezone()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+                    .into(),
+            ],
+        )
+        .await?;
+        info!(
+            "applied migration {}: {}",
+            migration.version, migration.description
+        );
+        migrated.push(migration);
+    }
+    Ok(migrated)
+}
@@ -1,13 +1,93 @@
-use crate::db::user::{add, exist};
-use crate::types::{Age, Email, Introduction, Password, Role, Sex, Sid, Status, Username};
-use log::info;
-use rbatis::rbatis::Rbatis;
+use crate::config;
+use crate::db::user::{add, admins_with_password, count_admins, exist};
+use crate::error::Error;
+use crate::metrics::DbTimer;
+use crate::types::{Age, Email, Introduction, Password, Role, Sex, Status, Username};
+use crate::CONFIG;
+use log::{debug, info, warn};
+use rbatis::core::db::DBPoolOptions;
+use rbatis::executor::RBatisTxExecutor;
+use rbatis::rbatis::{Rbatis, RbatisOption};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::Duration;
+pub mod audit;
 pub mod book;
+pub mod metadata;
+pub mod migration;
 pub mod record;
+#[cfg(test)]
+pub mod testing;
 pub mod user;
 
 lazy_static::lazy_static! {
-    static ref RB:Rbatis=Rbatis::new();
+    static ref RB:Rbatis=Rbatis::new_with_opt(RbatisOption {
+        log_plugin: Arc::new(Box::new(DbTimer::default())),
+        ..Default::default()
+    });
+}
+
+//未提交就被丢弃（出错提前返回、请求因关闭超时被取消）的事务在后台回滚，
+//避免连接带着未结束的事务回到连接池
+pub struct Tx(Option<RBatisTxExecutor<'static>>);
+
+impl Tx {
+    pub async fn begin() -> Result<Tx, rbatis::Error> {
+        Ok(Tx(Some(RB.acquire_begin().await?)))
+    }
+
+    pub async fn commit(mut self) -> Result<(), rbatis::Error> {
+        self.deref_mut().commit().await
+    }
+
+    pub async fn rollback(mut self) -> Result<(), rbatis::Error> {
+        self.deref_mut().rollback().await
+    }
+}
+
+//在一个事务中执行 f，成功时提交，用于命令行等不写审计日志的修改
+pub async fn transaction<T, F>(f: F) -> Result<T, Error>
+where
+    F: AsyncFnOnce(&mut Tx) -> Result<T, Error>,
+{
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
+    let mut tx = Tx::begin().await.map_err(map_err)?;
+    let value = f(&mut tx).await?;
+    tx.commit().await.map_err(map_err)?;
+    Ok(value)
+}
+
+impl Deref for Tx {
+    type Target = RBatisTxExecutor<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl Drop for Tx {
+    fn drop(&mut self) {
+        let mut tx = match self.0.take() {
+            Some(tx) if !tx.is_done() => tx,
+            _ => return,
+        };
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                if let Err(e) = tx.rollback().await {
+                    warn!("failed to roll back transaction: {e}");
+                }
+            });
+        }
+    }
 }
 
 const MYSQL_TABLE_USER: &str = "
@@ -21,7 +101,13 @@ CREATE TABLE IF NOT EXISTS `user`(
     `sex` VARCHAR(255),
     `role` TINYINT,
     `status` TINYINT,
-    PRIMARY KEY ( `email` )
+    `avatar` VARCHAR(255),
+    `must_change_password` TINYINT DEFAULT 0,
+    `locale` VARCHAR(16),
+    PRIMARY KEY ( `email` ),
+    INDEX `idx_user_username` ( `username` ),
+    INDEX `idx_user_sid` ( `sid` ),
+    INDEX `idx_user_role_status` ( `role`, `status` )
 )ENGINE=InnoDB DEFAULT CHARSET=utf8;";
 
 const MYSQL_TABLE_BOOK: &str = "
@@ -32,9 +118,84 @@ CREATE TABLE IF NOT EXISTS `book`(
     `press` VARCHAR(255),
     `remain` INT,
     `stock` INT,
+    `publication_year` INT,
+    `edition` VARCHAR(255),
+    `language` VARCHAR(255),
+    `page_count` INT,
+    `description` TEXT,
+    `call_number` VARCHAR(255),
+    `shelf_location` VARCHAR(255),
+    `cover` VARCHAR(255),
+    `status` TINYINT DEFAULT 0,
+    `withdrawn_date` DATETIME,
+    `withdrawn_reason` VARCHAR(255),
+    PRIMARY KEY ( `isbn` ),
+    INDEX `idx_book_call_number` ( `call_number` ),
+    INDEX `idx_book_status` ( `status` )
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+const MYSQL_TABLE_BOOK_CONTRIBUTOR: &str = "CREATE TABLE IF NOT EXISTS `book_contributor`(
+    `id`   BIGINT NOT NULL AUTO_INCREMENT,
+    `isbn` VARCHAR(13),
+    `name` VARCHAR(255),
+    `role` VARCHAR(20),
+    `position` INT,
+
+    PRIMARY KEY ( `id` ),
+    INDEX `idx_book_contributor_isbn` ( `isbn` ),
+    INDEX `idx_book_contributor_name` ( `name` ),
+
+    FOREIGN KEY (`isbn`)
+    REFERENCES book(`isbn`)
+    ON DELETE CASCADE
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+const MYSQL_TABLE_BOOK_SUBJECT: &str = "CREATE TABLE IF NOT EXISTS `book_subject`(
+    `id`   BIGINT NOT NULL AUTO_INCREMENT,
+    `isbn` VARCHAR(13),
+    `subject` VARCHAR(255),
+
+    PRIMARY KEY ( `id` ),
+    INDEX `idx_book_subject_isbn` ( `isbn` ),
+    INDEX `idx_book_subject_subject` ( `subject` ),
+
+    FOREIGN KEY (`isbn`)
+    REFERENCES book(`isbn`)
+    ON DELETE CASCADE
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+const MYSQL_TABLE_METADATA_CACHE: &str = "CREATE TABLE IF NOT EXISTS `book_metadata_cache`(
+    `isbn` VARCHAR(13),
+    `data` TEXT,
+    `fetched_date` DATETIME,
+
     PRIMARY KEY ( `isbn` )
 )ENGINE=InnoDB DEFAULT CHARSET=utf8;";
 
+//只追加的审计日志；MySQL 中没有触发器限制修改，生产环境建议只给应用账号授予该表的 INSERT、SELECT
+//以及 actor、target、diff、ip 四列的 UPDATE 权限（注销用户时改写，见 audit::redact）
+const MYSQL_TABLE_AUDIT_LOG: &str = "CREATE TABLE IF NOT EXISTS `audit_log`(
+    `id` BIGINT NOT NULL AUTO_INCREMENT,
+    `actor` VARCHAR(255) NOT NULL,
+    `action` VARCHAR(64) NOT NULL,
+    `target` VARCHAR(255) NOT NULL,
+    `diff` TEXT,
+    `ip` VARCHAR(45),
+    `created_date` DATETIME NOT NULL,
+    `failure_of` BIGINT,
+    `error` VARCHAR(255),
+
+    PRIMARY KEY ( `id` ),
+    INDEX `idx_audit_log_actor` (`actor`),
+    INDEX `idx_audit_log_action` (`action`),
+    INDEX `idx_audit_log_created_date` (`created_date`)
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
+const MYSQL_TABLE_AUDIT_REDACTION: &str = "CREATE TABLE IF NOT EXISTS `audit_redaction`(
+    `email` VARCHAR(255) NOT NULL,
+    `placeholder` VARCHAR(255) NOT NULL
+)ENGINE=InnoDB DEFAULT CHARSET=utf8;";
+
 const MYSQL_TABLE_BORROWED_BOOK: &str = "CREATE TABLE IF NOT EXISTS `borrowed_book`(
     `id`   BIGINT NOT NULL AUTO_INCREMENT,
     `isbn` VARCHAR(13),
@@ -42,16 +203,15 @@ const MYSQL_TABLE_BORROWED_BOOK: &str = "CREATE TABLE IF NOT EXISTS `borrowed_bo
     `book_name` VARCHAR(255),
     `borrowed_date` DATETIME,
     `return_date` DATETIME,
+    `borrow_operator` VARCHAR(255),
 
     PRIMARY KEY ( `id` ),
 
     FOREIGN KEY (`isbn`)
-    REFERENCES book(`isbn`)
-    ON DELETE CASCADE,
+    REFERENCES book(`isbn`),
 
     FOREIGN KEY (`email`)
     REFERENCES user(`email`)
-    ON DELETE CASCADE
 
 )ENGINE=InnoDB DEFAULT CHARSET=utf8;";
 
@@ -62,16 +222,16 @@ const MYSQL_TABLE_RETURN_BOOK: &str = "CREATE TABLE IF NOT EXISTS `return_book`(
     `book_name` VARCHAR(255),
     `borrowed_date` DATETIME,
     `return_date` DATETIME,
+    `borrow_operator` VARCHAR(255),
+    `return_operator` VARCHAR(255),
 
     PRIMARY KEY ( `id` ),
 
     FOREIGN KEY (`isbn`)
-    REFERENCES book(`isbn`)
-    ON DELETE CASCADE,
+    REFERENCES book(`isbn`),
 
     FOREIGN KEY (`email`)
     REFERENCES user(`email`)
-    ON DELETE CASCADE
 
 )ENGINE=InnoDB DEFAULT CHARSET=utf8;";
 
@@ -85,9 +245,18 @@ const SQLITE_TABLE_USER: &str = "CREATE TABLE IF NOT EXISTS `user`(
     `sex` VARCHAR(255),
     `role` TINYINT,
     `status` TINYINT,
+    `avatar` VARCHAR(255),
+    `must_change_password` TINYINT DEFAULT 0,
+    `locale` VARCHAR(16),
     PRIMARY KEY ( `email` )
 )";
 
+const SQLITE_INDEX_USER: [&str; 3] = [
+    "CREATE INDEX IF NOT EXISTS `idx_user_username` ON `user` ( `username` )",
+    "CREATE INDEX IF NOT EXISTS `idx_user_sid` ON `user` ( `sid` )",
+    "CREATE INDEX IF NOT EXISTS `idx_user_role_status` ON `user` ( `role`, `status` )",
+];
+
 const SQLITE_TABLE_BOOK: &str = "CREATE TABLE IF NOT EXISTS `book`(
     `name` VARCHAR(255),
     `author` VARCHAR(255),
@@ -95,9 +264,97 @@ const SQLITE_TABLE_BOOK: &str = "CREATE TABLE IF NOT EXISTS `book`(
     `press` VARCHAR(255),
     `remain` INT,
     `stock` INT,
+    `publication_year` INT,
+    `edition` VARCHAR(255),
+    `language` VARCHAR(255),
+    `page_count` INT,
+    `description` TEXT,
+    `call_number` VARCHAR(255),
+    `shelf_location` VARCHAR(255),
+    `cover` VARCHAR(255),
+    `status` TINYINT DEFAULT 0,
+    `withdrawn_date` DATETIME,
+    `withdrawn_reason` VARCHAR(255),
     PRIMARY KEY ( `isbn` )
 )";
 
+const SQLITE_TABLE_BOOK_CONTRIBUTOR: &str = "CREATE TABLE IF NOT EXISTS `book_contributor`(
+    `id`   INTEGER PRIMARY KEY ,
+    `isbn` VARCHAR(13),
+    `name` VARCHAR(255),
+    `role` VARCHAR(20),
+    `position` INT,
+
+    FOREIGN KEY (`isbn`)
+    REFERENCES book(`isbn`)
+    ON DELETE CASCADE
+)";
+
+const SQLITE_TABLE_BOOK_SUBJECT: &str = "CREATE TABLE IF NOT EXISTS `book_subject`(
+    `id`   INTEGER PRIMARY KEY ,
+    `isbn` VARCHAR(13),
+    `subject` VARCHAR(255),
+
+    FOREIGN KEY (`isbn`)
+    REFERENCES book(`isbn`)
+    ON DELETE CASCADE
+)";
+
+const SQLITE_INDEX_BOOK: [&str; 6] = [
+    "CREATE INDEX IF NOT EXISTS `idx_book_call_number` ON `book` ( `call_number` )",
+    "CREATE INDEX IF NOT EXISTS `idx_book_status` ON `book` ( `status` )",
+    "CREATE INDEX IF NOT EXISTS `idx_book_contributor_isbn` ON `book_contributor` ( `isbn` )",
+    "CREATE INDEX IF NOT EXISTS `idx_book_contributor_name` ON `book_contributor` ( `name` )",
+    "CREATE INDEX IF NOT EXISTS `idx_book_subject_isbn` ON `book_subject` ( `isbn` )",
+    "CREATE INDEX IF NOT EXISTS `idx_book_subject_subject` ON `book_subject` ( `subject` )",
+];
+
+const SQLITE_TABLE_METADATA_CACHE: &str = "CREATE TABLE IF NOT EXISTS `book_metadata_cache`(
+    `isbn` VARCHAR(13) PRIMARY KEY,
+    `data` TEXT,
+    `fetched_date` DATETIME
+)";
+
+const SQLITE_TABLE_AUDIT_LOG: &str = "CREATE TABLE IF NOT EXISTS `audit_log`(
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `actor` VARCHAR(255) NOT NULL,
+    `action` VARCHAR(64) NOT NULL,
+    `target` VARCHAR(255) NOT NULL,
+    `diff` TEXT,
+    `ip` VARCHAR(45),
+    `created_date` DATETIME NOT NULL,
+    `failure_of` INTEGER,
+    `error` VARCHAR(255)
+)";
+
+//注销用户时在事务中登记原邮箱和占位邮箱，触发器据此只放行 redact 所做的改写，事务结束前删除
+const SQLITE_TABLE_AUDIT_REDACTION: &str = "CREATE TABLE IF NOT EXISTS `audit_redaction`(
+    `email` VARCHAR(255) NOT NULL,
+    `placeholder` VARCHAR(255) NOT NULL
+)";
+
+//索引，以及拒绝修改和删除审计日志的触发器；只允许把登记的原邮箱改写为占位邮箱（actor 同时清空 ip，
+//target 同时清空 diff），或在 diff 中把原邮箱替换为占位邮箱，其它修改一律拒绝
+const SQLITE_INDEX_AUDIT_LOG: [&str; 5] = [
+    "CREATE INDEX IF NOT EXISTS `idx_audit_log_actor` ON `audit_log` (`actor`)",
+    "CREATE INDEX IF NOT EXISTS `idx_audit_log_action` ON `audit_log` (`action`)",
+    "CREATE INDEX IF NOT EXISTS `idx_audit_log_created_date` ON `audit_log` (`created_date`)",
+    "CREATE TRIGGER IF NOT EXISTS `audit_log_no_update` BEFORE UPDATE ON `audit_log`
+    WHEN NEW.`id` IS NOT OLD.`id` OR NEW.`action` IS NOT OLD.`action`
+        OR NEW.`created_date` IS NOT OLD.`created_date`
+        OR NEW.`failure_of` IS NOT OLD.`failure_of` OR NEW.`error` IS NOT OLD.`error`
+        OR NOT EXISTS (SELECT 1 FROM `audit_redaction` AS r
+            WHERE r.`placeholder` LIKE '%@anonymized.invalid'
+            AND (NEW.`actor` IS OLD.`actor` AND NEW.`ip` IS OLD.`ip`
+                OR OLD.`actor` = r.`email` AND NEW.`actor` = r.`placeholder` AND NEW.`ip` IS NULL)
+            AND (NEW.`target` IS OLD.`target`
+                    AND (NEW.`diff` IS OLD.`diff` OR NEW.`diff` = REPLACE(OLD.`diff`, r.`email`, r.`placeholder`))
+                OR OLD.`target` = r.`email` AND NEW.`target` = r.`placeholder` AND NEW.`diff` IS NULL))
+    BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END",
+    "CREATE TRIGGER IF NOT EXISTS `audit_log_no_delete` BEFORE DELETE ON `audit_log`
+    BEGIN SELECT RAISE(ABORT, 'audit_log is append-only'); END",
+];
+
 const SQLITE_TABLE_BORROWED_BOOK: &str = "CREATE TABLE IF NOT EXISTS `borrowed_book`(
     `id`   INTEGER PRIMARY KEY ,
     `isbn` VARCHAR(13),
@@ -105,14 +362,13 @@ const SQLITE_TABLE_BORROWED_BOOK: &str = "CREATE TABLE IF NOT EXISTS `borrowed_b
     `book_name` VARCHAR(255),
     `borrowed_date` DATETIME,
     `return_date` DATETIME,
-    
+    `borrow_operator` VARCHAR(255),
+
     FOREIGN KEY (`isbn`)
-    REFERENCES book(`isbn`)
-    ON DELETE CASCADE,
+    REFERENCES book(`isbn`),
 
     FOREIGN KEY (`email`)
     REFERENCES user(`email`)
-    ON DELETE CASCADE
 )";
 
 const SQLITE_TABLE_RETURN_BOOK: &str = "CREATE TABLE IF NOT EXISTS `return_book`(
@@ -122,52 +378,203 @@ const SQLITE_TABLE_RETURN_BOOK: &str = "CREATE TABLE IF NOT EXISTS `return_book`
     `book_name` VARCHAR(255),
     `borrowed_date` DATETIME,
     `return_date` DATETIME,
-    
+    `borrow_operator` VARCHAR(255),
+    `return_operator` VARCHAR(255),
+
     FOREIGN KEY (`isbn`)
-    REFERENCES book(`isbn`)
-    ON DELETE CASCADE,
+    REFERENCES book(`isbn`),
 
     FOREIGN KEY (`email`)
     REFERENCES user(`email`)
-    ON DELETE CASCADE
 )";
 
-pub async fn init_db(addr: &str) {
+//初始化时创建的全部数据表，readyz 据此检查表结构
+const TABLES: [&str; 10] = [
+    "user",
+    "book",
+    "book_contributor",
+    "book_subject",
+    "book_metadata_cache",
+    "audit_log",
+    "audit_redaction",
+    "borrowed_book",
+    "return_book",
+    "schema_migrations",
+];
+
+//重试间隔从 1 秒开始翻倍，最长 30 秒
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+async fn link(addr: &str) -> Result<(), rbatis::Error> {
+    let retries = CONFIG.db.connect_retries;
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let opts = DBPoolOptions {
+            connect_timeout: Duration::from_secs(CONFIG.db.connect_timeout),
+            ..Default::default()
+        };
+        match RB.link_opt(addr, opts).await {
+            Ok(()) => return Ok(()),
+            Err(e) if retries == 0 || attempt <= retries => {
+                warn!("failed to link db (attempt {attempt}): {e}, retry in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub async fn ping() -> Result<(), rbatis::Error> {
+    RB.fetch::<Option<i64>>("SELECT 1", vec![])
+        .await
+        .map(|_| ())
+}
+
+//返回缺失的数据表
+pub async fn missing_tables() -> Vec<&'static str> {
+    let mut missing = vec![];
+    for table in TABLES {
+        let sql = format!("SELECT 1 FROM `{table}` WHERE 1 = 0");
+        if RB.fetch::<Option<i64>>(&sql, vec![]).await.is_err() {
+            missing.push(table);
+        }
+    }
+    missing
+}
+
+pub async fn close() {
+    match RB.get_pool() {
+        Ok(pool) => pool.close().await,
+        Err(e) => debug!("{e}"),
+    }
+}
+
+//逐表读出全部数据用于备份，不依赖 mysqldump 等外部工具
+pub async fn dump() -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+    let mut tables = serde_json::Map::new();
+    for table in TABLES {
+        let sql = format!("SELECT * FROM `{table}`");
+        let rows = RB
+            .fetch::<Vec<serde_json::Value>>(&sql, vec![])
+            .await
+            .map_err(|e| {
+                debug!("{e}");
+                Error::DbError
+            })?;
+        tables.insert(table.to_string(), rows.into());
+    }
+    Ok(tables)
+}
+
+pub async fn init_db(addr: &str) -> Result<(), rbatis::Error> {
     info!("link db {addr}");
-    RB.link(addr).await.unwrap();
+    link(addr).await?;
 
-    if &addr[0..6] == "sqlite" {
+    let sqlite = addr.starts_with("sqlite");
+    if sqlite {
         info!("create sqlite table if not exist");
-        RB.exec(SQLITE_TABLE_USER, vec![]).await.unwrap();
-        RB.exec(SQLITE_TABLE_BOOK, vec![]).await.unwrap();
-        RB.exec(SQLITE_TABLE_BORROWED_BOOK, vec![]).await.unwrap();
-        RB.exec(SQLITE_TABLE_RETURN_BOOK, vec![]).await.unwrap();
+        RB.exec(SQLITE_TABLE_USER, vec![]).await?;
+        RB.exec(SQLITE_TABLE_BOOK, vec![]).await?;
+        RB.exec(SQLITE_TABLE_BOOK_CONTRIBUTOR, vec![]).await?;
+        RB.exec(SQLITE_TABLE_BOOK_SUBJECT, vec![]).await?;
+        RB.exec(SQLITE_TABLE_METADATA_CACHE, vec![]).await?;
+        RB.exec(SQLITE_TABLE_AUDIT_LOG, vec![]).await?;
+        RB.exec(SQLITE_TABLE_AUDIT_REDACTION, vec![]).await?;
+        RB.exec(SQLITE_TABLE_BORROWED_BOOK, vec![]).await?;
+        RB.exec(SQLITE_TABLE_RETURN_BOOK, vec![]).await?;
     }
-    if &addr[0..5] == "mysql" {
+    if addr.starts_with("mysql") {
         info!("create mysql table if not exist");
-        RB.exec(MYSQL_TABLE_USER, vec![]).await.unwrap();
-        RB.exec(MYSQL_TABLE_BOOK, vec![]).await.unwrap();
-        RB.exec(MYSQL_TABLE_BORROWED_BOOK, vec![]).await.unwrap();
-        RB.exec(MYSQL_TABLE_RETURN_BOOK, vec![]).await.unwrap();
-    }
-
-    if exist(&Email::from("admin@admin.com")).await.is_none() {
-        info!("admin is not exist, create admin account");
-        info!("email: admin@admin.com");
-        info!("password: asdc1234ASD");
-
-        let user = user::User {
-            username: Username::from("admin"),
-            password: Password::from("asdc1234ASD"),
-            sid: Sid::from("100000000000"),
-            email: Email::from("admin@admin.com"),
-            introduction: Introduction::from("admin"),
-            age: Age::from(18),
-            sex: Sex::from("unknown"),
-            role: Role::Admin,
-            status: Status::Enabled,
-        };
+        RB.exec(MYSQL_TABLE_USER, vec![]).await?;
+        RB.exec(MYSQL_TABLE_BOOK, vec![]).await?;
+        RB.exec(MYSQL_TABLE_BOOK_CONTRIBUTOR, vec![]).await?;
+        RB.exec(MYSQL_TABLE_BOOK_SUBJECT, vec![]).await?;
+        RB.exec(MYSQL_TABLE_METADATA_CACHE, vec![]).await?;
+        RB.exec(MYSQL_TABLE_AUDIT_LOG, vec![]).await?;
+        RB.exec(MYSQL_TABLE_AUDIT_REDACTION, vec![]).await?;
+        RB.exec(MYSQL_TABLE_BORROWED_BOOK, vec![]).await?;
+        RB.exec(MYSQL_TABLE_RETURN_BOOK, vec![]).await?;
+    }
+
+    //已有的数据库先补齐新增的列，再创建依赖这些列的索引
+    migration::migrate(sqlite).await?;
+    if sqlite {
+        for index in SQLITE_INDEX_USER
+            .iter()
+            .chain(&SQLITE_INDEX_BOOK)
+            .chain(&SQLITE_INDEX_AUDIT_LOG)
+        {
+            RB.exec(index, vec![]).await?;
+        }
+    }
+
+    Ok(())
+}
+
+//数据库中没有管理员时，按配置创建初始管理员；随机密码只在标准错误输出一次，不写入日志
+pub async fn bootstrap_admin(admin: &config::Admin) -> Result<(), Error> {
+    if count_admins().await? > 0 {
+        return Ok(());
+    }
+    let email = match &admin.email {
+        Some(email) => Email::from(email.as_str()),
+        None => {
+            warn!("no admin account, run `backend create-admin <email>` or set admin.email");
+            return Ok(());
+        }
+    };
+    if exist(&email).await.is_some() {
+        return Err(Error::UserAlreadyExist);
+    }
+
+    let password = Password::generate();
+    let user = user::User {
+        username: Username::from(admin.username.as_str()),
+        password: password.clone(),
+        sid: user::unused_sid().await?,
+        email: email.clone(),
+        introduction: Introduction::from(""),
+        age: Age::from(0),
+        sex: Sex::from("unknown"),
+        role: Role::Admin,
+        status: Status::Enabled,
+        avatar: None,
+        must_change_password: true,
+        locale: None,
+    };
+    transaction(async |tx| {
+        add(tx, user, Some(Role::Admin))
+            .await
+            .ok_or(Error::FailedToRegister)
+    })
+    .await?;
+    warn!(
+        "created admin {}, the generated password is printed to stderr",
+        email.as_str()
+    );
+    eprintln!(
+        "admin {} password: {} (must be changed on first login)",
+        email.as_str(),
+        password.as_str()
+    );
+    Ok(())
+}
+
+//旧版本在新数据库上创建的默认管理员密码
+const KNOWN_DEFAULT_PASSWORDS: [&str; 1] = ["asdc1234ASD"];
 
-        add(user, Some(Role::Admin)).await.unwrap();
+pub async fn warn_default_passwords() -> Result<(), Error> {
+    for password in KNOWN_DEFAULT_PASSWORDS {
+        for email in admins_with_password(&Password::from(password)).await? {
+            warn!(
+                "admin {} is still using a default password, change it or run `backend reset-password {}`",
+                email.as_str(),
+                email.as_str()
+            );
+        }
     }
+    Ok(())
 }
@@ -1,13 +1,17 @@
 use super::book::{verify_borrow, Book};
 use super::user::exist;
+use crate::config::current;
 use crate::error::Error;
-use crate::types::{Bookname, Email, Isbn};
-use chrono::{Local, NaiveDateTime, Utc};
+use crate::types::{BookStatus, Bookname, Email, Isbn};
+use chrono::{Duration, Local, NaiveDateTime, Utc};
 use log::debug;
-use rbatis::crud::CRUD;
-use rbatis::{crud_table, sql};
+use rbatis::crud::{CRUDMut, CRUD};
+use rbatis::crud_table;
+use rbatis::executor::ExecutorMut;
+use rbatis::plugin::page::{Page, PageRequest};
+use rbatis::wrapper::Wrapper;
 
-use super::RB;
+use super::{Tx, RB};
 
 #[crud_table(table_name:borrowed_book)]
 pub struct BorrowedBook {
@@ -16,6 +20,8 @@ pub struct BorrowedBook {
     pub book_name: Bookname,
     pub borrowed_date: NaiveDateTime,
     pub return_date: Option<NaiveDateTime>,
+    //代为办理借阅的工作人员，读者自助借阅时为空
+    pub borrow_operator: Option<Email>,
 }
 
 #[crud_table(table_name:return_book)]
@@ -25,6 +31,9 @@ pub struct ReturnBook {
     pub book_name: Bookname,
     pub borrowed_date: NaiveDateTime,
     pub return_date: Option<NaiveDateTime>,
+    pub borrow_operator: Option<Email>,
+    //代为办理归还的工作人员，读者自助归还时为空
+    pub return_operator: Option<Email>,
 }
 
 pub async fn list_borrowed_book(email: &Email) -> Result<Vec<BorrowedBook>, Error> {
@@ -45,21 +54,130 @@ pub async fn list_return_book(email: &Email) -> Result<Vec<ReturnBook>, Error> {
         })
 }
 
-fn now_with_timezone() -> NaiveDateTime {
+pub fn now_with_timezone() -> NaiveDateTime {
     Utc::now().with_timezone(&Local).naive_local()
 }
 
-pub async fn borrow(email: &Email, isbns: &[Isbn]) -> Result<(), Error> {
-    exist(email).await.ok_or(Error::UserNotExist)?;
-    verify_borrow(isbns).await?;
-    let books = RB
-        .fetch_list_by_column::<Book, _>("isbn", isbns)
+//借阅到期时间，由 policy.loan_days 决定
+pub fn due_date(borrowed_date: &NaiveDateTime) -> NaiveDateTime {
+    *borrowed_date + Duration::days(current().policy.loan_days.into())
+}
+
+pub fn is_overdue(borrowed_date: &NaiveDateTime) -> bool {
+    due_date(borrowed_date) < now_with_timezone()
+}
+
+//早于该时间借出且未归还的书籍即为逾期
+fn overdue_threshold() -> NaiveDateTime {
+    now_with_timezone() - Duration::days(current().policy.loan_days.into())
+}
+
+#[derive(Debug, Default)]
+pub struct LoanFilter {
+    pub isbn: Option<Isbn>,
+    pub email: Option<Email>,
+    pub overdue: Option<bool>,
+}
+
+pub async fn list_loans(
+    filter: &LoanFilter,
+    page_no: u64,
+    page_size: u64,
+) -> Result<Page<BorrowedBook>, Error> {
+    let threshold = overdue_threshold();
+    let w = RB
+        .new_wrapper()
+        .do_if(filter.isbn.is_some(), |w| w.eq("isbn", &filter.isbn))
+        .do_if(filter.email.is_some(), |w| w.eq("email", &filter.email))
+        .do_if(filter.overdue == Some(true), |w| {
+            w.lt("borrowed_date", threshold)
+        })
+        .do_if(filter.overdue == Some(false), |w| {
+            w.ge("borrowed_date", threshold)
+        })
+        .order_by(true, &["borrowed_date"]);
+
+    RB.fetch_page_by_wrapper::<BorrowedBook>(w, &PageRequest::new(page_no, page_size))
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
+pub async fn list_holders(isbn: &Isbn) -> Result<Vec<BorrowedBook>, Error> {
+    let w = RB
+        .new_wrapper()
+        .eq("isbn", isbn)
+        .order_by(true, &["borrowed_date"]);
+    RB.fetch_list_by_wrapper::<BorrowedBook>(w)
         .await
         .map_err(|e| {
             debug!("{e}");
             Error::DbError
-        })?;
+        })
+}
+
+#[derive(Debug)]
+pub struct LoanSummary {
+    pub active: u64,
+    pub overdue: u64,
+    pub returned: u64,
+}
+
+pub async fn loan_summary(email: &Email) -> Result<LoanSummary, Error> {
+    summarize(RB.new_wrapper().eq("email", email)).await
+}
+
+//所有用户的借阅统计
+pub async fn loan_totals() -> Result<LoanSummary, Error> {
+    summarize(RB.new_wrapper()).await
+}
+
+async fn summarize(w: Wrapper) -> Result<LoanSummary, Error> {
+    let db_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
+
+    let active = RB
+        .fetch_count_by_wrapper::<BorrowedBook>(w.clone())
+        .await
+        .map_err(db_err)?;
+    let overdue = RB
+        .fetch_count_by_wrapper::<BorrowedBook>(w.clone().lt("borrowed_date", overdue_threshold()))
+        .await
+        .map_err(db_err)?;
+    let returned = RB
+        .fetch_count_by_wrapper::<ReturnBook>(w)
+        .await
+        .map_err(db_err)?;
+
+    Ok(LoanSummary {
+        active,
+        overdue,
+        returned,
+    })
+}
+
+//借出前的检查给出具体的错误，写入在调用方的事务中进行，并在扣减库存时再次确认有余量
+pub async fn borrow(
+    tx: &mut Tx,
+    email: &Email,
+    isbns: &[Isbn],
+    operator: Option<&Email>,
+) -> Result<(), Error> {
+    exist(email).await.ok_or(Error::UserNotExist)?;
+    verify_borrow(isbns).await?;
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
 
+    let books = tx
+        .fetch_list_by_column::<Book, _>("isbn", isbns)
+        .await
+        .map_err(map_err)?;
     let records: Vec<BorrowedBook> = books
         .into_iter()
         .map(|book| BorrowedBook {
@@ -68,52 +186,74 @@ pub async fn borrow(email: &Email, isbns: &[Isbn]) -> Result<(), Error> {
             book_name: book.name,
             borrowed_date: now_with_timezone(),
             return_date: None,
+            borrow_operator: operator.cloned(),
         })
         .collect();
+    tx.save_batch(&records, &[]).await.map_err(map_err)?;
 
-    RB.save_batch(&records, &[])
-        .await
-        .map(|r| {
-            debug!("{r:?}");
-        })
-        .map_err(|e| {
-            debug!("Error: {e}");
-            Error::DbError
-        })?;
+    let sql = "UPDATE book SET remain = remain - 1 WHERE isbn = ? AND remain > 0 AND status = ?";
+    for isbn in isbns {
+        let args = vec![
+            isbn.as_str().into(),
+            rbson::Bson::Int32(BookStatus::Available as i32),
+        ];
+        if tx.exec(sql, args).await.map_err(map_err)?.rows_affected != 1 {
+            return Err(Error::NoRemainBook);
+        }
+    }
+    Ok(())
+}
 
-    #[sql(RB, "UPDATE book SET remain=remain-1 WHERE isbn = ?")]
-    async fn update_remain(isbn: &Isbn) -> () {}
+//把借阅记录（包括代办人）转移到另一个邮箱下，用于注销用户，在调用方的事务中执行
+pub async fn reassign_loans(tx: &mut Tx, from: &Email, to: &Email) -> Result<(), Error> {
+    const UPDATES: [&str; 5] = [
+        "UPDATE borrowed_book SET email = ? WHERE email = ?",
+        "UPDATE borrowed_book SET borrow_operator = ? WHERE borrow_operator = ?",
+        "UPDATE return_book SET email = ? WHERE email = ?",
+        "UPDATE return_book SET borrow_operator = ? WHERE borrow_operator = ?",
+        "UPDATE return_book SET return_operator = ? WHERE return_operator = ?",
+    ];
 
-    for isbn in isbns {
-        update_remain(isbn).await.map_err(|e| {
-            debug!("{e}");
-            Error::InternalErr
-        })?;
+    for sql in UPDATES {
+        tx.exec(sql, vec![to.as_str().into(), from.as_str().into()])
+            .await
+            .map_err(|e| {
+                debug!("{e}");
+                Error::DbError
+            })?;
     }
 
     Ok(())
 }
 
-pub async fn return_book(email: &Email, isbns: &[Isbn]) -> Result<(), Error> {
-    //verify_return(email, isbns).await?;
-    //获取以前的还书记录
+//借阅记录移到已还表并归还库存，在调用方的事务中完成；并发的重复归还只有一次生效
+pub async fn return_book(
+    tx: &mut Tx,
+    email: &Email,
+    isbns: &[Isbn],
+    operator: Option<&Email>,
+) -> Result<(), Error> {
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
     let w = RB
         .new_wrapper_table::<BorrowedBook>()
         .in_array("isbn", isbns)
         .eq("email", email);
-    let v = RB
+
+    let v = tx
         .fetch_list_by_wrapper::<BorrowedBook>(w.clone())
         .await
-        .map_err(|e| {
-            debug!("{e}");
-            Error::DbError
-        })?;
+        .map_err(map_err)?;
+    let removed = tx
+        .remove_by_wrapper::<BorrowedBook>(w)
+        .await
+        .map_err(map_err)?;
+    if v.is_empty() || removed != v.len() as u64 {
+        return Err(Error::BookIsNotBorrowed);
+    }
 
-    //将记录移到已还表上
-    RB.remove_by_wrapper::<BorrowedBook>(w).await.map_err(|e| {
-        debug!("{e}");
-        Error::DbError
-    })?;
     let records: Vec<ReturnBook> = v
         .into_iter()
         .map(|record| ReturnBook {
@@ -122,22 +262,127 @@ pub async fn return_book(email: &Email, isbns: &[Isbn]) -> Result<(), Error> {
             book_name: record.book_name,
             borrowed_date: record.borrowed_date,
             return_date: Some(now_with_timezone()),
+            borrow_operator: record.borrow_operator,
+            return_operator: operator.cloned(),
         })
         .collect();
-    RB.save_batch(&records, &[]).await.map_err(|e| {
-        debug!("{e}");
-        Error::DbError
-    })?;
+    tx.save_batch(&records, &[]).await.map_err(map_err)?;
 
-    #[sql(RB, "UPDATE book SET remain=remain+1 WHERE isbn = ?")]
-    async fn update_remain(isbn: &Isbn) -> () {}
+    //只归还实际借出的书籍的库存
+    for record in &records {
+        tx.exec(
+            "UPDATE book SET remain = remain + 1 WHERE isbn = ?",
+            vec![record.isbn.as_str().into()],
+        )
+        .await
+        .map_err(map_err)?;
+    }
+    Ok(())
+}
 
-    for isbn in isbns {
-        update_remain(isbn).await.map_err(|e| {
-            debug!("{e}");
-            Error::InternalErr
-        })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::book::query_by_isbn;
+    use crate::db::testing::{insert_book, insert_user, run};
+    use crate::db::transaction;
+    use crate::types::Role;
+
+    #[test]
+    fn page_loans() {
+        run(async {
+            let email = Email::from("pager@loan.test");
+            insert_user(email.as_str(), "202300000101", Role::User).await;
+            let isbns: Vec<Isbn> = ["9780198534532", "9780851310411", "9780901690548"]
+                .iter()
+                .map(|isbn| isbn.parse().unwrap())
+                .collect();
+            for isbn in &isbns {
+                insert_book(isbn.as_str(), 1).await;
+            }
+            transaction(async |tx| borrow(tx, &email, &isbns, None).await)
+                .await
+                .unwrap();
+            //把其中一本改为早于借阅期限借出
+            let long_ago = now_with_timezone() - Duration::days(365);
+            RB.exec(
+                "UPDATE borrowed_book SET borrowed_date = ? WHERE isbn = ?",
+                vec![rbson::to_bson(&long_ago).unwrap(), isbns[0].as_str().into()],
+            )
+            .await
+            .unwrap();
+
+            let filter = LoanFilter {
+                email: Some(email.clone()),
+                ..Default::default()
+            };
+            //total 是筛选后的总数，不受分页影响
+            let page = list_loans(&filter, 2, 2).await.unwrap();
+            assert_eq!(page.total, 3);
+            assert_eq!(page.records.len(), 1);
+
+            let overdue = LoanFilter {
+                overdue: Some(true),
+                ..filter
+            };
+            let page = list_loans(&overdue, 1, 10).await.unwrap();
+            assert_eq!(page.total, 1);
+            assert_eq!(page.records[0].isbn, isbns[0]);
+
+            let summary = loan_summary(&email).await.unwrap();
+            assert_eq!((summary.active, summary.overdue), (3, 1));
+        })
     }
 
-    Ok(())
+    #[test]
+    fn desk_checkout_and_checkin() {
+        run(async {
+            let patron = Email::from("patron@desk.test");
+            let other = Email::from("other@desk.test");
+            let staff = Email::from("staff@desk.test");
+            insert_user(patron.as_str(), "202300000201", Role::User).await;
+            insert_user(other.as_str(), "202300000202", Role::User).await;
+            let isbn: Isbn = "9780999999912".parse().unwrap();
+            insert_book(isbn.as_str(), 1).await;
+            let isbns = [isbn.clone()];
+            let remain = async || {
+                let book = query_by_isbn(&isbn).await.unwrap();
+                book.remain.as_u32()
+            };
+
+            transaction(async |tx| borrow(tx, &patron, &isbns, Some(&staff)).await)
+                .await
+                .unwrap();
+            assert_eq!(remain().await, 0);
+            let loans = list_borrowed_book(&patron).await.unwrap();
+            assert_eq!(loans[0].borrow_operator.as_ref(), Some(&staff));
+
+            //没有余量时整体失败，不留下借阅记录
+            let err = transaction(async |tx| borrow(tx, &other, &isbns, Some(&staff)).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::NoRemainBook), "{err:?}");
+            assert!(list_borrowed_book(&other).await.unwrap().is_empty());
+
+            //只能归还本人借阅的书籍
+            let err = transaction(async |tx| return_book(tx, &other, &isbns, Some(&staff)).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::BookIsNotBorrowed), "{err:?}");
+
+            transaction(async |tx| return_book(tx, &patron, &isbns, Some(&staff)).await)
+                .await
+                .unwrap();
+            assert_eq!(remain().await, 1);
+            assert!(list_borrowed_book(&patron).await.unwrap().is_empty());
+            let returned = list_return_book(&patron).await.unwrap();
+            assert_eq!(returned[0].borrow_operator.as_ref(), Some(&staff));
+            assert_eq!(returned[0].return_operator.as_ref(), Some(&staff));
+
+            let err = transaction(async |tx| return_book(tx, &patron, &isbns, None).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::BookIsNotBorrowed), "{err:?}");
+        })
+    }
 }
@@ -0,0 +1,110 @@
+//单元测试共用的 sqlite 文件数据库。连接池绑定在创建它的运行时上，
+//所以数据库测试都通过 run 在同一个运行时中依次执行，各测试使用不同的邮箱和 ISBN 避免相互影响
+use super::audit::{search, AuditFilter, AuditLog};
+use super::book::{add as add_book, Book};
+use super::user::{add as add_user, User};
+use super::{init_db, transaction};
+use crate::audit::Audit;
+use crate::types::{
+    Author, BookStatus, Bookname, Email, Introduction, Isbn, Password, Press, Role, Sex, Sid,
+    Status, Stock, Username,
+};
+use poem::web::RemoteAddr;
+use poem::Addr;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::runtime::Runtime;
+
+lazy_static::lazy_static! {
+    static ref RUNTIME: Runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    //是否已经建好数据库
+    static ref SERIAL: Mutex<bool> = Mutex::new(false);
+}
+
+pub fn run<F: Future>(f: F) -> F::Output {
+    //某个测试失败不影响其它测试继续使用数据库
+    let mut initialized = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    RUNTIME.block_on(async {
+        if !*initialized {
+            let path = std::env::temp_dir().join(format!("library-test-{}.db", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            std::fs::File::create(&path).unwrap();
+            init_db(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap();
+            *initialized = true;
+        }
+        f.await
+    })
+}
+
+pub fn user(email: &str, sid: &str) -> User {
+    User {
+        username: Username::from("tester"),
+        password: Password::from("Abcd1234"),
+        sid: Sid::from(sid),
+        email: Email::from(email),
+        introduction: Introduction::from("hi"),
+        age: 20.into(),
+        sex: Sex::from("male"),
+        role: Role::User,
+        status: Status::Enabled,
+        avatar: None,
+        must_change_password: false,
+        locale: None,
+    }
+}
+
+pub async fn insert_user(email: &str, sid: &str, role: Role) {
+    transaction(async |tx| Ok(add_user(tx, user(email, sid), Some(role)).await))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+pub fn book(isbn: &str, stock: u32) -> Book {
+    Book {
+        name: Bookname::from("测试书籍"),
+        author: Author::from("作者"),
+        isbn: isbn.parse::<Isbn>().unwrap(),
+        press: Press::from("出版社"),
+        stock: Stock::from(stock),
+        remain: Stock::from(stock),
+        publication_year: None,
+        edition: None,
+        language: None,
+        page_count: None,
+        description: None,
+        call_number: None,
+        shelf_location: None,
+        cover: None,
+        status: BookStatus::Available,
+        withdrawn_date: None,
+        withdrawn_reason: None,
+    }
+}
+
+pub async fn insert_book(isbn: &str, stock: u32) {
+    transaction(async |tx| add_book(tx, book(isbn, stock), &[], &[]).await)
+        .await
+        .unwrap();
+}
+
+pub fn audit(actor: &str) -> Audit {
+    let addr = RemoteAddr(Addr::SocketAddr("10.0.0.1:1234".parse().unwrap()));
+    Audit::new(&Email::from(actor), &addr)
+}
+
+//按写入顺序返回以 target 为对象的审计日志
+pub async fn audit_logs(target: &str) -> Vec<AuditLog> {
+    let filter = AuditFilter {
+        target: Some(target.to_string()),
+        ..Default::default()
+    };
+    let mut logs = search(&filter, 1, 10).await.unwrap().records;
+    logs.reverse();
+    logs
+}
@@ -1,9 +1,16 @@
-use super::RB;
+use super::audit::redact;
+use super::record::{list_borrowed_book, reassign_loans};
+use super::{Tx, RB};
 use crate::error::Error;
+use crate::i18n::Locale;
 use crate::types::{Age, Email, Introduction, Password, Role, Sex, Sid, Status, Username};
+use crate::upload::remove_image;
 use log::debug;
-use rbatis::crud::{Skip, CRUD};
+use rand::Rng;
+use rbatis::crud::{CRUDMut, Skip, CRUD};
 use rbatis::crud_table;
+use rbatis::plugin::page::{Page, PageRequest};
+use rbatis::wrapper::Wrapper;
 use serde::{Deserialize, Serialize};
 
 #[crud_table(table_name:user)]
@@ -18,10 +25,18 @@ pub struct User {
     pub sex: Sex,
     pub role: Role,
     pub status: Status,
+    //头像文件名，见 upload 模块
+    pub avatar: Option<String>,
+    //使用生成的或管理员设置的密码时为 true，修改密码前只能访问少数接口
+    #[serde(default, deserialize_with = "crate::types::bool_from_int")]
+    pub must_change_password: bool,
+    //错误消息使用的语言，未设置时按 Accept-Language 选择
+    #[serde(default, deserialize_with = "crate::i18n::optional_locale")]
+    pub locale: Option<Locale>,
 }
 
 #[crud_table(table_name:user)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct UpdateUser {
     pub username: Option<Username>,
     pub password: Option<Password>,
@@ -31,6 +46,10 @@ pub struct UpdateUser {
     pub sex: Option<Sex>,
     pub role: Option<Role>,
     pub status: Option<Status>,
+    pub avatar: Option<String>,
+    pub must_change_password: Option<bool>,
+    //空字符串表示清除语言设置
+    pub locale: Option<String>,
 }
 
 pub async fn exist(email: &Email) -> Option<()> {
@@ -43,12 +62,44 @@ pub async fn query(email: &Email) -> Option<User> {
         .ok()?
 }
 
+pub async fn query_by_sid(sid: &Sid) -> Result<Vec<User>, Error> {
+    RB.fetch_list_by_column::<User, _>("sid", &[sid])
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
+//为未指定学号的管理员生成一个未被占用的 12 位学号，以 9 开头便于和学生区分
+pub async fn unused_sid() -> Result<Sid, Error> {
+    loop {
+        let n: u64 = rand::thread_rng().gen_range(0..100_000_000_000);
+        let sid = Sid::from(format!("9{n:011}").as_str());
+        if query_by_sid(&sid).await?.is_empty() {
+            return Ok(sid);
+        }
+    }
+}
+
+pub async fn query_batch(emails: &[Email]) -> Result<Vec<User>, Error> {
+    if emails.is_empty() {
+        return Ok(vec![]);
+    }
+    RB.fetch_list_by_column::<User, _>("email", emails)
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
 //default role: User
-pub async fn add(mut user: User, role: Option<Role>) -> Option<()> {
+pub async fn add(tx: &mut Tx, mut user: User, role: Option<Role>) -> Option<()> {
     user.password = user.password.encode();
     let role = role.map_or(Role::User, |r| r);
     user.role = role;
-    RB.save(&user, &[]).await.is_ok().then(|| ())
+    tx.save(&user, &[]).await.is_ok().then(|| ())
 }
 
 pub async fn verify(email: &Email, password: &Password) -> Option<User> {
@@ -61,6 +112,37 @@ pub async fn verify(email: &Email, password: &Password) -> Option<User> {
     }
 }
 
+pub async fn count() -> Result<u64, Error> {
+    RB.fetch_count::<User>().await.map_err(|e| {
+        debug!("{e}");
+        Error::DbError
+    })
+}
+
+pub async fn count_admins() -> Result<u64, Error> {
+    let w = RB.new_wrapper().eq("role", Role::Admin);
+    RB.fetch_count_by_wrapper::<User>(w).await.map_err(|e| {
+        debug!("{e}");
+        Error::DbError
+    })
+}
+
+//仍在使用该密码的管理员
+pub async fn admins_with_password(password: &Password) -> Result<Vec<Email>, Error> {
+    let w = RB
+        .new_wrapper()
+        .eq("role", Role::Admin)
+        .and()
+        .eq("password", password.encode());
+    RB.fetch_list_by_wrapper::<User>(w)
+        .await
+        .map(|users| users.into_iter().map(|user| user.email).collect())
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
 pub async fn list() -> Result<Vec<User>, Error> {
     RB.fetch_list::<User>().await.map_err(|e| {
         debug!("{e}");
@@ -68,13 +150,64 @@ pub async fn list() -> Result<Vec<User>, Error> {
     })
 }
 
-pub async fn update(email: &Email, mut user: UpdateUser) -> Result<(), Error> {
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub sid: Option<String>,
+    pub role: Option<Role>,
+    pub status: Option<Status>,
+}
+
+//用户输入中的 `%`、`_` 按字面匹配，以 `!` 作为 ESCAPE 字符以兼容 sqlite 和 mysql
+fn like_escaped(w: Wrapper, column: &str, value: &Option<String>) -> Wrapper {
+    let value = value
+        .as_deref()
+        .unwrap_or_default()
+        .replace('!', "!!")
+        .replace('%', "!%")
+        .replace('_', "!_");
+    w.and().push(
+        &format!("{column} LIKE ? ESCAPE '!'"),
+        vec![rbson::Bson::String(format!("%{value}%"))],
+    )
+}
+
+pub async fn search(
+    filter: &UserFilter,
+    page_no: u64,
+    page_size: u64,
+) -> Result<Page<User>, Error> {
+    let w = RB
+        .new_wrapper()
+        .do_if(filter.username.is_some(), |w| {
+            like_escaped(w, "username", &filter.username)
+        })
+        .do_if(filter.email.is_some(), |w| {
+            like_escaped(w, "email", &filter.email)
+        })
+        .do_if(filter.sid.is_some(), |w| {
+            like_escaped(w, "sid", &filter.sid)
+        })
+        .do_if(filter.role.is_some(), |w| w.eq("role", &filter.role))
+        .do_if(filter.status.is_some(), |w| w.eq("status", &filter.status))
+        .order_by(true, &["email"]);
+
+    RB.fetch_page_by_wrapper::<User>(w, &PageRequest::new(page_no, page_size))
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::DbError
+        })
+}
+
+pub async fn update(tx: &mut Tx, email: &Email, mut user: UpdateUser) -> Result<(), Error> {
     exist(email).await.ok_or(Error::UserNotExist)?;
     if let Some(password) = user.password {
         user.password = Some(password.encode());
     }
     let w = RB.new_wrapper().eq("email", email);
-    RB.update_by_wrapper(&user, w, &[Skip::Value(rbatis::Value::Null)])
+    tx.update_by_wrapper(&user, w, &[Skip::Value(rbatis::Value::Null)])
         .await
         .map_err(|e| {
             debug!("{e}");
@@ -82,3 +215,165 @@ pub async fn update(email: &Email, mut user: UpdateUser) -> Result<(), Error> {
         })
         .map(|_| ())
 }
+
+//注销后的占位邮箱，使用保留的 .invalid 顶级域名
+pub fn placeholder_email() -> Email {
+    let id: u64 = rand::thread_rng().gen();
+    Email::from(format!("deleted-{id:016x}@anonymized.invalid").as_str())
+}
+
+//头像按内容命名，其它用户或书籍仍在使用同一图片时保留文件
+pub async fn remove_unused_image(name: &str) {
+    let sql = "SELECT (SELECT COUNT(1) FROM user WHERE avatar = ?)
+        + (SELECT COUNT(1) FROM book WHERE cover = ?)";
+    match RB.fetch::<u64>(sql, vec![name.into(), name.into()]).await {
+        Ok(0) => remove_image(name).await,
+        Ok(_) => {}
+        Err(e) => debug!("{e}"),
+    }
+}
+
+//注销已停用的用户：以占位信息替换个人信息，借阅记录和审计日志转移到占位邮箱下以保留统计；
+//返回原头像，事务提交后由调用方以 remove_unused_image 删除
+pub async fn anonymize(
+    tx: &mut Tx,
+    email: &Email,
+    placeholder: &Email,
+) -> Result<Option<String>, Error> {
+    let user = query(email).await.ok_or(Error::UserNotExist)?;
+    if user.status != Status::Disabled {
+        return Err(Error::UserIsNotDeactivated);
+    }
+    if !list_borrowed_book(email).await?.is_empty() {
+        return Err(Error::UserHasActiveLoans);
+    }
+
+    let anonymous = User {
+        username: Username::from("已注销用户"),
+        password: Password::generate().encode(),
+        sid: Sid::from("000000000000"),
+        email: placeholder.clone(),
+        introduction: Introduction::from(""),
+        age: Age::from(0),
+        sex: Sex::from("unknown"),
+        role: Role::User,
+        status: Status::Disabled,
+        avatar: None,
+        must_change_password: false,
+        locale: None,
+    };
+    let map_err = |e: rbatis::Error| {
+        debug!("{e}");
+        Error::DbError
+    };
+    //占位用户、借阅记录、审计日志和原用户在同一事务中修改，中途失败不会留下只完成一半的注销
+    tx.save(&anonymous, &[]).await.map_err(map_err)?;
+    reassign_loans(tx, email, placeholder).await?;
+    redact(tx, email, placeholder).await?;
+    tx.remove_by_column::<User, _>("email", email)
+        .await
+        .map_err(map_err)?;
+    Ok(user.avatar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::record::{borrow, list_return_book, return_book};
+    use crate::db::testing::{insert_book, insert_user, run};
+    use crate::db::transaction;
+    use crate::types::Isbn;
+
+    fn emails(page: &Page<User>) -> Vec<&str> {
+        page.records.iter().map(|u| u.email.as_str()).collect()
+    }
+
+    #[test]
+    fn search_users() {
+        run(async {
+            insert_user("s1_x@search.test", "202300000001", Role::User).await;
+            insert_user("s1zx@search.test", "202300000002", Role::User).await;
+            insert_user("s2@search.test", "202300000003", Role::Librarian).await;
+
+            let filter = |email: &str| UserFilter {
+                email: Some(email.to_string()),
+                ..Default::default()
+            };
+            //`_` 和 `%` 按字面匹配
+            let page = search(&filter("1_x"), 1, 10).await.unwrap();
+            assert_eq!(emails(&page), ["s1_x@search.test"]);
+            let page = search(&filter("%"), 1, 10).await.unwrap();
+            assert!(page.records.is_empty());
+
+            let page = search(
+                &UserFilter {
+                    role: Some(Role::Librarian),
+                    ..filter("search.test")
+                },
+                1,
+                10,
+            )
+            .await
+            .unwrap();
+            assert_eq!(emails(&page), ["s2@search.test"]);
+
+            let page = search(
+                &UserFilter {
+                    sid: Some("2023000000".into()),
+                    ..Default::default()
+                },
+                2,
+                2,
+            )
+            .await
+            .unwrap();
+            assert_eq!(page.total, 3);
+            assert_eq!(emails(&page), ["s2@search.test"]);
+        })
+    }
+
+    #[test]
+    fn anonymize_user() {
+        run(async {
+            let email = Email::from("gone@anonymize.test");
+            let isbn: Isbn = "9787115123459".parse().unwrap();
+            insert_user(email.as_str(), "202300000601", Role::User).await;
+            insert_book(isbn.as_str(), 1).await;
+            let isbns = [isbn];
+            transaction(async |tx| borrow(tx, &email, &isbns, None).await)
+                .await
+                .unwrap();
+            let placeholder = placeholder_email();
+
+            let err = transaction(async |tx| anonymize(tx, &email, &placeholder).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::UserIsNotDeactivated), "{err:?}");
+            let disable = UpdateUser {
+                status: Some(Status::Disabled),
+                ..Default::default()
+            };
+            transaction(async |tx| update(tx, &email, disable).await)
+                .await
+                .unwrap();
+            let err = transaction(async |tx| anonymize(tx, &email, &placeholder).await)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::UserHasActiveLoans), "{err:?}");
+
+            transaction(async |tx| return_book(tx, &email, &isbns, None).await)
+                .await
+                .unwrap();
+            transaction(async |tx| anonymize(tx, &email, &placeholder).await)
+                .await
+                .unwrap();
+            assert!(query(&email).await.is_none());
+            let anonymous = query(&placeholder).await.unwrap();
+            assert_eq!(anonymous.username.as_str(), "已注销用户");
+            assert_eq!(anonymous.status, Status::Disabled);
+            //借阅历史保留在占位邮箱下
+            assert!(list_return_book(&email).await.unwrap().is_empty());
+            assert_eq!(list_return_book(&placeholder).await.unwrap().len(), 1);
+        })
+    }
+}
@@ -1,94 +1,222 @@
+use crate::config::current;
+use crate::i18n;
 use poem::error::ResponseError;
 use poem::http::StatusCode;
-use serde::Deserialize;
-use validator::ValidationErrors;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use validator::{ValidationErrors, ValidationErrorsKind};
 
 pub const SUCCESS_CODE: u32 = 20000;
-fn fmt(code: u32, message: &str) -> String {
-    format!("{{\"code\":{code},\"message\":\"{message}\"}}")
-}
 
-#[derive(Debug, thiserror::Error, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone)]
 pub enum Error {
-    #[error("{}", fmt(40000, "邮箱或密码输入错误"))]
     InvalidEmailOrPassword,
-
-    #[error("{}", fmt(50012, "无效的Token，请重新登录后重试"))]
     InvalidlToken,
-
-    #[error("{}", fmt(50011, "Token 创建失败，请稍后再试"))]
     FailedToCreateToken,
-
-    #[error("{}", fmt(30000, "用户已存在"))]
     UserAlreadyExist,
-
-    #[error("{}", fmt(30001, "用户不存在"))]
     UserNotExist,
-
-    #[error("{}", fmt(10000, "注册失败"))]
+    UserHasActiveLoans,
+    UserIsNotDeactivated,
+    PasswordChangeRequired,
     FailedToRegister,
-
-    #[error("{}", fmt(60000, "内部错误"))]
     InternalErr,
-
-    #[error("{}", fmt(700000, "账号被禁用"))]
     AccountWasDisabled,
-
-    #[error("{}", fmt(80000, "书籍已存在"))]
     BookAlreadyExist,
-
-    #[error("{}", fmt(80001, "无法添加书籍"))]
     FailedToAddBook,
-
-    #[error("{}", fmt(80002, "书籍不存在"))]
     BookNotExist,
-
-    #[error("{}", fmt(80003, "删除书籍失败"))]
     FailedToDeleteBook,
-
-    #[error("{}", fmt(90000, "无效的请求，请重新登录后重试"))]
+    BookIsOnLoan,
+    BookWasWithdrawn,
+    BookIsNotWithdrawn,
     InvalidlRequest,
-
-    #[error("{}", fmt(100000, "当前的用户不是管理员，无权操作"))]
     RoleNotAdmin,
-
-    #[error("{}", fmt(110000, "书籍列表为空"))]
+    RoleNotStaff,
     BookListWasEmpty,
-
-    #[error(
-        "{{\"code\":{code},\"message\":\"{message} {0}\"}}",
-        code = 120000,
-        message = "不合法的数据，请检查你的输入"
-    )]
     InvalidData(String),
-
-    #[error("{}", fmt(130000, "非法的 ISBN 号"))]
     InvalidIsbn,
-
-    #[error("{}", fmt(140000, "没有剩余书籍"))]
     NoRemainBook,
-
-    #[error("{}", fmt(150000, "数据库错误"))]
     DbError,
-
-    #[error("{}", fmt(160000, "你没有借过此书"))]
     BookIsNotBorrowed,
-
-    #[error("{}", fmt(170000, "库存不足"))]
     StockIsntEnough,
-
-    #[error("{}", fmt(180000, "错误的密码"))]
     InvalidPassword,
+    AmbiguousSid,
+    InvalidImage,
+    ImageTooLarge,
+    FailedToSaveImage,
+    MetadataNotFound,
+    MetadataProviderErr,
+    FailedToWriteAuditLog,
+    TooManyRequests,
+}
+
+//错误响应的 JSON，message 按当前请求的语言输出
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub code: u32,
+    pub message: String,
+}
+
+impl Error {
+    //业务码和 locales 中 [error] 段的键
+    fn describe(&self) -> (u32, &'static str) {
+        use Error::*;
+        match self {
+            InvalidEmailOrPassword => (40000, "invalid_email_or_password"),
+            InvalidlToken => (50012, "invalid_token"),
+            FailedToCreateToken => (50011, "failed_to_create_token"),
+            UserAlreadyExist => (30000, "user_already_exist"),
+            UserNotExist => (30001, "user_not_exist"),
+            UserHasActiveLoans => (30002, "user_has_active_loans"),
+            UserIsNotDeactivated => (30003, "user_is_not_deactivated"),
+            PasswordChangeRequired => (30004, "password_change_required"),
+            FailedToRegister => (10000, "failed_to_register"),
+            InternalErr => (60000, "internal"),
+            AccountWasDisabled => (700000, "account_was_disabled"),
+            BookAlreadyExist => (80000, "book_already_exist"),
+            FailedToAddBook => (80001, "failed_to_add_book"),
+            BookNotExist => (80002, "book_not_exist"),
+            FailedToDeleteBook => (80003, "failed_to_delete_book"),
+            BookIsOnLoan => (80004, "book_is_on_loan"),
+            BookWasWithdrawn => (80005, "book_was_withdrawn"),
+            BookIsNotWithdrawn => (80006, "book_is_not_withdrawn"),
+            InvalidlRequest => (90000, "invalid_request"),
+            RoleNotAdmin => (100000, "role_not_admin"),
+            RoleNotStaff => (100001, "role_not_staff"),
+            BookListWasEmpty => (110000, "book_list_was_empty"),
+            InvalidData(_) => (120000, "invalid_data"),
+            InvalidIsbn => (130000, "invalid_isbn"),
+            NoRemainBook => (140000, "no_remain_book"),
+            DbError => (150000, "db"),
+            BookIsNotBorrowed => (160000, "book_is_not_borrowed"),
+            StockIsntEnough => (170000, "stock_isnt_enough"),
+            InvalidPassword => (180000, "invalid_password"),
+            AmbiguousSid => (190000, "ambiguous_sid"),
+            InvalidImage => (200000, "invalid_image"),
+            ImageTooLarge => (200001, "image_too_large"),
+            FailedToSaveImage => (200002, "failed_to_save_image"),
+            MetadataNotFound => (210000, "metadata_not_found"),
+            MetadataProviderErr => (210001, "metadata_provider"),
+            FailedToWriteAuditLog => (220000, "failed_to_write_audit_log"),
+            TooManyRequests => (230000, "too_many_requests"),
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        self.describe().0
+    }
+
+    pub fn message(&self) -> String {
+        let message = i18n::message(&format!("error.{}", self.describe().1));
+        match self {
+            Error::InvalidData(detail) => format!("{message} {detail}"),
+            _ => message,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.message(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let body = serde_json::to_string(&self.body()).map_err(|_| fmt::Error)?;
+        f.write_str(&body)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    fn http_status(&self) -> StatusCode {
+        use Error::*;
+        match self {
+            InvalidEmailOrPassword | InvalidlToken | InvalidlRequest => StatusCode::UNAUTHORIZED,
+            AccountWasDisabled | PasswordChangeRequired | RoleNotAdmin | RoleNotStaff => {
+                StatusCode::FORBIDDEN
+            }
+            UserNotExist | BookNotExist | BookListWasEmpty | MetadataNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            UserAlreadyExist | UserHasActiveLoans | UserIsNotDeactivated | BookAlreadyExist
+            | BookIsOnLoan | BookWasWithdrawn | BookIsNotWithdrawn | NoRemainBook
+            | BookIsNotBorrowed | StockIsntEnough | AmbiguousSid => StatusCode::CONFLICT,
+            InvalidData(_) | InvalidIsbn | InvalidImage | ImageTooLarge | InvalidPassword => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            MetadataProviderErr => StatusCode::BAD_GATEWAY,
+            FailedToCreateToken
+            | FailedToRegister
+            | InternalErr
+            | FailedToAddBook
+            | FailedToDeleteBook
+            | DbError
+            | FailedToSaveImage
+            | FailedToWriteAuditLog => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl ResponseError for Error {
+    //未开启 global.http_error_status 时沿用旧行为，只通过响应体中的 code 区分错误
     fn status(&self) -> StatusCode {
-        StatusCode::OK
+        if current().global.http_error_status {
+            self.http_status()
+        } else {
+            StatusCode::OK
+        }
     }
 }
 
+//校验错误(含嵌套字段和列表)中是否存在指定的错误码
+fn has_code(errs: &ValidationErrors, code: &str) -> bool {
+    errs.errors().values().any(|kind| match kind {
+        ValidationErrorsKind::Field(errs) => errs.iter().any(|e| e.code == code),
+        ValidationErrorsKind::Struct(errs) => has_code(errs, code),
+        ValidationErrorsKind::List(list) => list.values().any(|errs| has_code(errs, code)),
+    })
+}
+
 impl From<ValidationErrors> for Error {
     fn from(v: ValidationErrors) -> Self {
-        Self::InvalidData(v.to_string())
+        //ISBN 校验失败时返回专门的错误码，便于客户端区分
+        if has_code(&v, "isbn") {
+            return Self::InvalidIsbn;
+        }
+        Self::InvalidData(i18n::validation_errors(&v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_http_status() {
+        let cases = [
+            (Error::InvalidlToken, StatusCode::UNAUTHORIZED),
+            (Error::InvalidEmailOrPassword, StatusCode::UNAUTHORIZED),
+            (Error::RoleNotAdmin, StatusCode::FORBIDDEN),
+            (Error::PasswordChangeRequired, StatusCode::FORBIDDEN),
+            (Error::BookNotExist, StatusCode::NOT_FOUND),
+            (Error::BookIsOnLoan, StatusCode::CONFLICT),
+            (
+                Error::InvalidData(String::new()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (Error::InvalidIsbn, StatusCode::UNPROCESSABLE_ENTITY),
+            //修改密码时旧密码错误属于输入错误，不是权限问题
+            (Error::InvalidPassword, StatusCode::UNPROCESSABLE_ENTITY),
+            (Error::TooManyRequests, StatusCode::TOO_MANY_REQUESTS),
+            (Error::MetadataProviderErr, StatusCode::BAD_GATEWAY),
+            (Error::DbError, StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (err, status) in cases {
+            assert_eq!(err.http_status(), status, "{err:?}");
+        }
     }
 }
@@ -0,0 +1,272 @@
+//错误和校验消息的多语言目录，每个请求按用户设置或 Accept-Language 选择语言
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use toml::value::{Table, Value};
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+    #[serde(rename = "en-US")]
+    EnUs,
+}
+
+impl Locale {
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::ZhCn => "zh-CN",
+            Locale::EnUs => "en-US",
+        }
+    }
+
+    //只比较语言部分，如 zh-TW、zh 都使用 zh-CN
+    fn from_language(tag: &str) -> Option<Locale> {
+        let language = tag.split(['-', '_']).next()?.trim();
+        if language.eq_ignore_ascii_case("zh") {
+            Some(Locale::ZhCn)
+        } else if language.eq_ignore_ascii_case("en") {
+            Some(Locale::EnUs)
+        } else {
+            None
+        }
+    }
+
+    //按 Accept-Language 中的权重选择支持的语言，如 `en-US,en;q=0.9,zh-CN;q=0.8`
+    pub fn negotiate(header: &str) -> Option<Locale> {
+        let mut ranges: Vec<(f32, &str)> = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let q = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((q, tag))
+            })
+            .filter(|(q, _)| *q > 0.0)
+            .collect();
+        //sort_by 是稳定排序，权重相同时保持原顺序
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranges
+            .into_iter()
+            .find_map(|(_, tag)| Locale::from_language(tag))
+    }
+}
+
+impl FromStr for Locale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Locale::ZhCn, Locale::EnUs]
+            .into_iter()
+            .find(|locale| locale.tag().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+//数据库中为空或无法识别的语言视为未设置
+pub fn optional_locale<'de, D>(deserializer: D) -> Result<Option<Locale>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = Option::<String>::deserialize(deserializer)?;
+    Ok(s.and_then(|s| s.parse().ok()))
+}
+
+tokio::task_local! {
+    static LOCALE: Locale;
+}
+
+//当前请求使用的语言，命令行等不在请求中时使用默认语言
+pub fn current() -> Locale {
+    LOCALE.try_with(|locale| *locale).unwrap_or_default()
+}
+
+pub async fn scope<F: Future>(locale: Locale, f: F) -> F::Output {
+    LOCALE.scope(locale, f).await
+}
+
+//把 [error] 段中的 key 展开为 error.key
+fn flatten(prefix: &str, table: Table, catalog: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            Value::Table(table) => flatten(&key, table, catalog),
+            Value::String(s) => {
+                catalog.insert(key, s);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn load(source: &str) -> HashMap<String, String> {
+    let mut catalog = HashMap::new();
+    flatten("", toml::from_str(source).unwrap(), &mut catalog);
+    catalog
+}
+
+lazy_static::lazy_static! {
+    static ref CATALOGS: HashMap<Locale, HashMap<String, String>> = HashMap::from([
+        (Locale::ZhCn, load(include_str!("../locales/zh-CN.toml"))),
+        (Locale::EnUs, load(include_str!("../locales/en-US.toml"))),
+    ]);
+}
+
+//当前语言缺少的消息使用默认语言
+fn lookup(key: &str) -> Option<&'static str> {
+    [current(), Locale::default()]
+        .iter()
+        .find_map(|locale| CATALOGS[locale].get(key))
+        .map(String::as_str)
+}
+
+pub fn message(key: &str) -> String {
+    lookup(key).unwrap_or(key).to_string()
+}
+
+//替换消息中 {name} 形式的占位符
+pub fn format(key: &str, args: &[(&str, String)]) -> String {
+    args.iter().fold(message(key), |message, (name, value)| {
+        message.replace(&format!("{{{name}}}"), value)
+    })
+}
+
+fn field_name(field: &str) -> String {
+    lookup(&format!("field.{field}"))
+        .unwrap_or(field)
+        .to_string()
+}
+
+//length、range 按给出的上下限选择消息，自定义的校验以 code 作为消息的键
+fn validation_message(field: &str, err: &ValidationError) -> String {
+    //derive 的 range 校验以浮点数保存上下限，整数按整数输出
+    let param = |name: &str| {
+        err.params
+            .get(name)
+            .map(|v| match (v.as_str(), v.as_f64()) {
+                (Some(s), _) => s.to_string(),
+                (None, Some(n)) if n.fract() == 0.0 => format!("{}", n as i64),
+                _ => v.to_string(),
+            })
+    };
+    let field = field_name(&param("name").unwrap_or_else(|| field.to_string()));
+    let (min, max) = (param("min"), param("max"));
+    let key = match (err.code.as_ref(), &min, &max) {
+        (code @ ("length" | "range"), Some(_), None) => format!("validation.{code}_min"),
+        (code @ ("length" | "range"), None, Some(_)) => format!("validation.{code}_max"),
+        (code, _, _) => format!("validation.{code}"),
+    };
+    let mut args = vec![("field", field)];
+    args.extend(min.map(|min| ("min", min)));
+    args.extend(max.map(|max| ("max", max)));
+    format(&key, &args)
+}
+
+fn collect(field: &str, kind: &ValidationErrorsKind, messages: &mut Vec<String>) {
+    match kind {
+        ValidationErrorsKind::Field(errs) => {
+            for err in errs {
+                messages.push(format!("{field}: {}", validation_message(field, err)));
+            }
+        }
+        //newtype 字段的错误嵌套在同名字段下，只报告外层的字段名
+        ValidationErrorsKind::Struct(errs) => {
+            for kind in errs.errors().values() {
+                collect(field, kind, messages);
+            }
+        }
+        ValidationErrorsKind::List(list) => {
+            for (i, errs) in list {
+                for kind in errs.errors().values() {
+                    collect(&format!("{field}[{i}]"), kind, messages);
+                }
+            }
+        }
+    }
+}
+
+//按当前语言输出校验错误，每个字段一条
+pub fn validation_errors(errs: &ValidationErrors) -> String {
+    let mut messages = vec![];
+    let mut fields: Vec<_> = errs.errors().iter().collect();
+    fields.sort_by_key(|(field, _)| *field);
+    for (field, kind) in fields {
+        collect(field, kind, &mut messages);
+    }
+    messages.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_locale() {
+        let cases = [
+            ("en-US,en;q=0.9,zh-CN;q=0.8", Some(Locale::EnUs)),
+            ("zh-CN,zh;q=0.9,en;q=0.8", Some(Locale::ZhCn)),
+            ("zh-TW", Some(Locale::ZhCn)),
+            ("EN", Some(Locale::EnUs)),
+            ("en_GB", Some(Locale::EnUs)),
+            //按权重而不是出现顺序选择
+            ("en;q=0.5, zh;q=0.8", Some(Locale::ZhCn)),
+            //权重为 0 表示不接受
+            ("en;q=0, zh-CN;q=0.1", Some(Locale::ZhCn)),
+            ("fr-FR,de;q=0.9,en;q=0.1", Some(Locale::EnUs)),
+            ("fr-FR,de;q=0.9", None),
+            ("*", None),
+            ("", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(Locale::negotiate(header), expected, "{header}");
+        }
+    }
+
+    #[test]
+    fn parse_locale() {
+        assert_eq!("en-us".parse(), Ok(Locale::EnUs));
+        assert_eq!("zh-CN".parse(), Ok(Locale::ZhCn));
+        assert_eq!("en".parse::<Locale>(), Err(()));
+    }
+
+    #[test]
+    fn catalogs_have_same_keys() {
+        let mut zh: Vec<_> = CATALOGS[&Locale::ZhCn].keys().collect();
+        let mut en: Vec<_> = CATALOGS[&Locale::EnUs].keys().collect();
+        zh.sort();
+        en.sort();
+        assert_eq!(zh, en);
+    }
+
+    #[tokio::test]
+    async fn message_in_scope() {
+        //不在请求中时使用默认语言
+        assert_eq!(message("error.invalid_isbn"), "非法的 ISBN 号");
+        let en = scope(Locale::EnUs, async {
+            (
+                message("error.invalid_isbn"),
+                format("detail.invalid_json", &[("error", "eof".into())]),
+                message("error.no_such_key"),
+            )
+        })
+        .await;
+        assert_eq!(
+            en,
+            (
+                "Invalid ISBN".to_string(),
+                "Invalid JSON: eof".to_string(),
+                "error.no_such_key".to_string()
+            )
+        );
+    }
+}
@@ -1,11 +1,22 @@
 pub mod api;
+pub mod audit;
 pub mod auth;
+pub mod bulk;
+pub mod cli;
 pub mod config;
 pub mod db;
 pub mod embed;
 pub mod error;
+pub mod i18n;
+pub mod marc;
+pub mod metadata;
+pub mod metrics;
 pub mod middleware;
+pub mod reload;
+pub mod shutdown;
+pub mod tls;
 pub mod types;
+pub mod upload;
 
 lazy_static::lazy_static! {
     pub static ref CONFIG:config::Config =  {
@@ -13,7 +24,15 @@ lazy_static::lazy_static! {
     };
 }
 
-pub async fn init() {
-    middleware::init_log(&CONFIG.global.log);
-    db::init_db(&CONFIG.db.addr).await;
+pub async fn init() -> Result<(), Box<dyn std::error::Error>> {
+    middleware::init_log(&CONFIG.global.log, std::io::stdout);
+    db::init_db(&CONFIG.db.addr).await?;
+    db::bootstrap_admin(&CONFIG.admin).await?;
+    db::warn_default_passwords().await?;
+    Ok(())
+}
+
+pub async fn init_cli() -> Result<(), rbatis::Error> {
+    middleware::init_log(&CONFIG.global.log, std::io::stderr);
+    db::init_db(&CONFIG.db.addr).await
 }
@@ -1,31 +1,80 @@
-use api::admin::book::{add_book, delete, update as update_book};
-use api::admin::user::{list, update as update_user};
+use api::admin::audit::search as search_audit;
+use api::admin::book::{
+    add_book, delete, export_books, import_books, lookup, restore, update as update_book,
+    upload_cover,
+};
+use api::admin::loan::{checkin, checkout, holders, list as list_loan, user_summary};
+use api::admin::user::{
+    anonymize, deactivate, import_users, list, search as search_user, update as update_user,
+};
 use api::book::borrow::borrow_book;
 use api::book::borrow_record::list_borrow;
 use api::book::list::get_list;
 use api::book::return_book::return_book;
 use api::book::return_record::list_return;
 use api::book::search::search_list;
+use api::health::{healthz, readyz};
+use api::user::export::export as export_user_data;
 use api::user::info::get_info;
 use api::user::login::login;
 use api::user::logout::logout;
 use api::user::register::register;
-use api::user::update::{change_password, update as update_user_info};
+use api::user::update::{change_password, set_locale, update as update_user_info, upload_avatar};
+use backend::cli::{self, Cli, Command};
 use backend::embed::Assets;
-use backend::{api, init, middleware, CONFIG};
-use log::info;
-use poem::endpoint::{EmbeddedFileEndpoint, EmbeddedFilesEndpoint};
+use backend::upload::UPLOAD_ROUTE;
+use backend::{api, config, init, init_cli, metrics, middleware, reload, shutdown, tls, CONFIG};
+use clap::Parser;
+use futures_util::future::try_join_all;
+use futures_util::FutureExt;
+use log::{info, warn};
+use poem::endpoint::{EmbeddedFileEndpoint, EmbeddedFilesEndpoint, StaticFilesEndpoint};
 use poem::listener::TcpListener;
+use poem::middleware::SizeLimit;
 use poem::{get, post, EndpointExt, Result, Route, Server};
+use std::time::Duration;
+
+//批量导入的请求体整体读入内存，按 Content-Length 限制大小
+const MAX_IMPORT_SIZE: usize = 20 * 1024 * 1024;
 
-#[rustfmt::skip]
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    init().await;
+async fn main() -> cli::CliResult {
+    let cli = Cli::parse();
+    if let Some(path) = cli.config {
+        config::set_path(path);
+    }
+    if let Some(Command::Config { command }) = cli.command {
+        if let Err(e) = cli::config(command) {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    lazy_static::initialize(&CONFIG);
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            init().await?;
+            serve().await?;
+            shutdown::finish().await
+        }
+        command => {
+            init_cli().await?;
+            cli::run(command).await?
+        }
+    }
+
+    Ok(())
+}
 
+#[rustfmt::skip]
+async fn serve() -> Result<(), std::io::Error> {
     let app = Route::new()
         .nest("/", EmbeddedFilesEndpoint::<Assets>::new())
         .nest("/index.html", EmbeddedFileEndpoint::<Assets>::new("index.html"))
+        .nest(UPLOAD_ROUTE, StaticFilesEndpoint::new(&CONFIG.upload.dir))
+        .nest("/healthz", get(healthz))
+        .nest("/readyz", get(readyz))
 
         .nest("/prod-api/books-manager/user/register", post(register))
         .nest("/prod-api/books-manager/user/login", post(login))
@@ -33,6 +82,9 @@ async fn main() -> Result<(), std::io::Error> {
         .nest("/prod-api/books-manager/user/logout", get(logout))
         .nest("/prod-api/books-manager/user/update",post(update_user_info))
         .nest("/prod-api/books-manager/user/change_password",post(change_password))
+        .nest("/prod-api/books-manager/user/avatar", post(upload_avatar))
+        .nest("/prod-api/books-manager/user/locale", post(set_locale))
+        .nest("/prod-api/books-manager/user/export", get(export_user_data))
 
         .nest("/prod-api/books-manager/book/search", post(search_list))
         .nest("/prod-api/books-manager/book/borrow", post(borrow_book))
@@ -42,15 +94,78 @@ async fn main() -> Result<(), std::io::Error> {
         .nest("/prod-api/books-manager/book/list_return", get(list_return))
 
         .nest("/prod-api/books-manager/admin/user/list", get(list))
+        .nest("/prod-api/books-manager/admin/user/search", post(search_user))
         .nest("/prod-api/books-manager/admin/book/delete", post(delete))
+        .nest("/prod-api/books-manager/admin/book/restore", post(restore))
         .nest("/prod-api/books-manager/admin/book/add", post(add_book))
         .nest("/prod-api/books-manager/admin/book/update",post(update_book))
+        .nest("/prod-api/books-manager/admin/book/import", post(import_books).with(SizeLimit::new(MAX_IMPORT_SIZE)))
+        .nest("/prod-api/books-manager/admin/book/export", get(export_books))
+        .nest("/prod-api/books-manager/admin/book/cover", post(upload_cover))
+        .nest("/prod-api/books-manager/admin/book/lookup", get(lookup))
         .nest("/prod-api/books-manager/admin/user/update",post(update_user))
+        .nest("/prod-api/books-manager/admin/user/import", post(import_users).with(SizeLimit::new(MAX_IMPORT_SIZE)))
+        .nest("/prod-api/books-manager/admin/user/deactivate", post(deactivate))
+        .nest("/prod-api/books-manager/admin/user/anonymize", post(anonymize))
+        .nest("/prod-api/books-manager/admin/loan/list", post(list_loan))
+        .nest("/prod-api/books-manager/admin/loan/holders", post(holders))
+        .nest("/prod-api/books-manager/admin/loan/user_summary", post(user_summary))
+        .nest("/prod-api/books-manager/admin/loan/checkout", post(checkout))
+        .nest("/prod-api/books-manager/admin/loan/checkin", post(checkin))
+        .nest("/prod-api/books-manager/admin/audit/search", post(search_audit))
 
+        .around(middleware::rate_limit)
+        .around(middleware::token)
+        .around(middleware::ip_rate_limit)
         .around(middleware::log)
-        .around(middleware::token);
+        .around(middleware::in_flight)
+        .around(middleware::locale);
+
+    reload::spawn_watcher();
+    shutdown::spawn_listener();
+    middleware::spawn_bucket_sweeper();
+    let timeout = Some(Duration::from_secs(CONFIG.global.shutdown_timeout));
+    let app = app.with_if(tls::enabled() && CONFIG.global.hsts_max_age > 0, tls::hsts());
+    let mut servers = vec![];
+
+    if let Some(redirect_addr) = &CONFIG.global.redirect_addr {
+        info!("redirect http://{redirect_addr} to https");
+        let server = Server::new(TcpListener::bind(redirect_addr))
+            .run_with_graceful_shutdown(tls::redirect, shutdown::requested(), timeout);
+        servers.push(server.boxed());
+    }
 
     let addr = &CONFIG.global.listen_addr;
-    info!("serve at http://{addr}");
-    Server::new(TcpListener::bind(addr)).run(app).await
+    let listener = tls::bind(addr)?;
+    info!("serve at {}://{addr}", if tls::enabled() { "https" } else { "http" });
+    if !CONFIG.metrics.enabled {
+        let server = Server::new(listener).run_with_graceful_shutdown(app, shutdown::requested(), timeout);
+        servers.push(server.boxed());
+        return try_join_all(servers).await.map(|_| ());
+    }
+
+    metrics::spawn_refresh();
+    match &CONFIG.metrics.listen_addr {
+        Some(metrics_addr) => {
+            info!("serve metrics at http://{metrics_addr}/metrics");
+            let metrics_app = Route::new().at("/metrics", get(metrics::export));
+            let metrics_server = Server::new(TcpListener::bind(metrics_addr))
+                .run_with_graceful_shutdown(metrics_app, shutdown::requested(), timeout);
+            servers.push(metrics_server.boxed());
+
+            let app = app.around(middleware::metrics);
+            let server = Server::new(listener).run_with_graceful_shutdown(app, shutdown::requested(), timeout);
+            servers.push(server.boxed());
+        }
+        None => {
+            warn!("metrics.listen_addr is empty, /metrics is served on the main listener");
+            let app = Route::new()
+                .at("/metrics", get(metrics::export))
+                .nest("/", app)
+                .around(middleware::metrics);
+            let server = Server::new(listener).run_with_graceful_shutdown(app, shutdown::requested(), timeout);
+            servers.push(server.boxed());
+        }
+    }
+    try_join_all(servers).await.map(|_| ())
 }
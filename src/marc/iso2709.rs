@@ -0,0 +1,169 @@
+use super::{is_control_tag, Field, Record, Subfield, LEADER_LEN};
+
+const SUBFIELD_DELIMITER: u8 = 0x1f;
+const FIELD_TERMINATOR: u8 = 0x1e;
+const RECORD_TERMINATOR: u8 = 0x1d;
+const DIRECTORY_ENTRY_LEN: usize = 12;
+//目录项中字段长度占 4 位，记录长度和起始位置占 5 位
+const MAX_FIELD_LEN: usize = 9999;
+const MAX_RECORD_LEN: usize = 99999;
+
+//按记录结束符切分，单条记录损坏不影响其它记录；只支持 UTF-8 编码
+pub fn parse(data: &[u8]) -> Vec<Result<Record, String>> {
+    data.split(|&b| b == RECORD_TERMINATOR)
+        .filter(|raw| !raw.iter().all(u8::is_ascii_whitespace))
+        .map(|raw| parse_record(trim_start(raw)))
+        .collect()
+}
+
+//记录之间可能夹带换行
+fn trim_start(raw: &[u8]) -> &[u8] {
+    let start = raw
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(raw.len());
+    &raw[start..]
+}
+
+fn parse_number(bytes: &[u8]) -> Result<usize, String> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("invalid number `{}`", String::from_utf8_lossy(bytes)))
+}
+
+fn to_string(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| "record is not valid UTF-8".to_string())
+}
+
+fn parse_record(raw: &[u8]) -> Result<Record, String> {
+    if raw.len() < LEADER_LEN {
+        return Err("record is shorter than the leader".into());
+    }
+    let leader = to_string(&raw[..LEADER_LEN])?;
+    let base = parse_number(&raw[12..17])?;
+    if base <= LEADER_LEN || base > raw.len() {
+        return Err(format!("invalid base address {base}"));
+    }
+
+    let directory = &raw[LEADER_LEN..base - 1];
+    if !directory.len().is_multiple_of(DIRECTORY_ENTRY_LEN) {
+        return Err("invalid directory length".into());
+    }
+
+    let mut fields = vec![];
+    for entry in directory.chunks(DIRECTORY_ENTRY_LEN) {
+        let tag = to_string(&entry[..3])?;
+        let len = parse_number(&entry[3..7])?;
+        let start = base + parse_number(&entry[7..12])?;
+        let data = raw
+            .get(start..start + len)
+            .ok_or_else(|| format!("field {tag} is out of range"))?;
+        let data = data.strip_suffix(&[FIELD_TERMINATOR]).unwrap_or(data);
+        fields.push(parse_field(tag, data)?);
+    }
+
+    Ok(Record { leader, fields })
+}
+
+fn parse_field(tag: String, data: &[u8]) -> Result<Field, String> {
+    if is_control_tag(&tag) {
+        return Ok(Field::Control {
+            value: to_string(data)?,
+            tag,
+        });
+    }
+
+    let mut parts = data.split(|&b| b == SUBFIELD_DELIMITER);
+    let indicators = to_string(parts.next().unwrap_or_default())?;
+    let mut indicators = indicators.chars();
+    let ind1 = indicators.next().unwrap_or(' ');
+    let ind2 = indicators.next().unwrap_or(' ');
+
+    let mut subfields = vec![];
+    for part in parts {
+        let part = to_string(part)?;
+        let mut chars = part.chars();
+        if let Some(code) = chars.next() {
+            subfields.push(Subfield {
+                code,
+                value: chars.as_str().to_string(),
+            });
+        }
+    }
+
+    Ok(Field::Data {
+        tag,
+        ind1,
+        ind2,
+        subfields,
+    })
+}
+
+//超出长度上限的记录无法用定长的目录表示，整体报错而不是写出错位的记录
+pub fn write(records: &[Record]) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    for record in records {
+        write_record(record, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn write_record(record: &Record, out: &mut Vec<u8>) -> Result<(), String> {
+    let id = record.control("001").unwrap_or_default();
+    let mut directory = vec![];
+    let mut data = vec![];
+
+    for field in &record.fields {
+        let start = data.len();
+        match field {
+            Field::Control { value, .. } => data.extend_from_slice(value.as_bytes()),
+            Field::Data {
+                ind1,
+                ind2,
+                subfields,
+                ..
+            } => {
+                data.extend_from_slice(format!("{ind1}{ind2}").as_bytes());
+                for subfield in subfields {
+                    data.push(SUBFIELD_DELIMITER);
+                    data.extend_from_slice(subfield.code.to_string().as_bytes());
+                    data.extend_from_slice(subfield.value.as_bytes());
+                }
+            }
+        }
+        data.push(FIELD_TERMINATOR);
+        if data.len() - start > MAX_FIELD_LEN {
+            return Err(format!(
+                "field {} of record {id} is longer than {MAX_FIELD_LEN} bytes",
+                field.tag()
+            ));
+        }
+        directory.extend_from_slice(
+            format!("{:0>3.3}{:04}{:05}", field.tag(), data.len() - start, start).as_bytes(),
+        );
+    }
+    directory.push(FIELD_TERMINATOR);
+
+    let base = LEADER_LEN + directory.len();
+    let len = base + data.len() + 1;
+    if len > MAX_RECORD_LEN {
+        return Err(format!("record {id} is longer than {MAX_RECORD_LEN} bytes"));
+    }
+    //重新计算记录长度和基地址，并声明为 UTF-8 编码
+    let mut leader = match record.leader.as_bytes() {
+        leader if leader.len() == LEADER_LEN && leader.is_ascii() => leader.to_vec(),
+        _ => Record::new().leader.into_bytes(),
+    };
+    leader[..5].copy_from_slice(format!("{len:05}").as_bytes());
+    leader[9] = b'a';
+    leader[10..12].copy_from_slice(b"22");
+    leader[12..17].copy_from_slice(format!("{base:05}").as_bytes());
+    leader[20..24].copy_from_slice(b"4500");
+
+    out.extend_from_slice(&leader);
+    out.extend_from_slice(&directory);
+    out.extend_from_slice(&data);
+    out.push(RECORD_TERMINATOR);
+    Ok(())
+}
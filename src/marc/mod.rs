@@ -0,0 +1,110 @@
+//MARC21 书目记录的通用表示，iso2709 和 xml 模块负责与两种交换格式互相转换
+pub mod iso2709;
+pub mod xml;
+
+pub const LEADER_LEN: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subfield {
+    pub code: char,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    //001~009 控制字段，没有指示符和子字段
+    Control {
+        tag: String,
+        value: String,
+    },
+    Data {
+        tag: String,
+        ind1: char,
+        ind2: char,
+        subfields: Vec<Subfield>,
+    },
+}
+
+impl Field {
+    pub fn tag(&self) -> &str {
+        match self {
+            Field::Control { tag, .. } | Field::Data { tag, .. } => tag,
+        }
+    }
+
+    pub fn data(tag: &str, ind1: char, ind2: char, subfields: &[(char, &str)]) -> Self {
+        Field::Data {
+            tag: tag.to_string(),
+            ind1,
+            ind2,
+            subfields: subfields
+                .iter()
+                .map(|&(code, value)| Subfield {
+                    code,
+                    value: value.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    //字段中第一个指定代码的子字段
+    pub fn subfield(&self, code: char) -> Option<&str> {
+        self.subfields(code).next()
+    }
+
+    pub fn subfields(&self, code: char) -> impl Iterator<Item = &str> {
+        let subfields: &[Subfield] = match self {
+            Field::Control { .. } => &[],
+            Field::Data { subfields, .. } => subfields,
+        };
+        subfields
+            .iter()
+            .filter(move |s| s.code == code)
+            .map(|s| s.value.as_str())
+    }
+}
+
+pub fn is_control_tag(tag: &str) -> bool {
+    tag.starts_with("00")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub leader: String,
+    pub fields: Vec<Field>,
+}
+
+impl Record {
+    //新建图书书目记录，长度和基地址在写出时由 iso2709 重新计算
+    pub fn new() -> Self {
+        Self {
+            leader: "00000nam a2200000 i 4500".to_string(),
+            fields: vec![],
+        }
+    }
+
+    pub fn control(&self, tag: &str) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            Field::Control { tag: t, value } if t == tag => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn fields<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Field> {
+        self.fields.iter().filter(move |field| field.tag() == tag)
+    }
+
+    pub fn field(&self, tag: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.tag() == tag)
+    }
+
+    pub fn push(&mut self, field: Field) {
+        self.fields.push(field);
+    }
+}
+
+impl Default for Record {
+    fn default() -> Self {
+        Self::new()
+    }
+}
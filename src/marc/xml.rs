@@ -0,0 +1,223 @@
+use super::{Field, Record, Subfield};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+const NAMESPACE: &str = "http://www.loc.gov/MARC21/slim";
+
+//正在读取文本内容的元素
+enum Target {
+    Leader,
+    Control(String),
+    Subfield(char),
+}
+
+//正在解析的记录，err 记录该条记录遇到的第一个错误
+#[derive(Default)]
+struct Current {
+    record: Record,
+    data: Option<Field>,
+    err: Option<String>,
+}
+
+fn attr(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == name)
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+fn indicator(e: &BytesStart, name: &[u8]) -> char {
+    attr(e, name).and_then(|v| v.chars().next()).unwrap_or(' ')
+}
+
+//XML 语法错误时整个文件无效，字段缺少属性时只有所在记录无效
+pub fn parse(data: &[u8]) -> Result<Vec<Result<Record, String>>, String> {
+    let mut reader = Reader::from_reader(data);
+    let mut buf = vec![];
+    let mut records = vec![];
+    let mut current: Option<Current> = None;
+    let mut target: Option<Target> = None;
+    let mut text = String::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| format!("invalid xml at {}: {e}", reader.buffer_position()))?;
+
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let empty = matches!(event, Event::Empty(_));
+                text.clear();
+                match e.local_name().as_ref() {
+                    b"record" => {
+                        current = Some(Current {
+                            record: Record {
+                                leader: String::new(),
+                                fields: vec![],
+                            },
+                            ..Default::default()
+                        })
+                    }
+                    b"leader" => target = Some(Target::Leader),
+                    b"controlfield" => match attr(e, b"tag") {
+                        Some(tag) => target = Some(Target::Control(tag)),
+                        None => set_err(&mut current, "controlfield without tag"),
+                    },
+                    b"datafield" => match attr(e, b"tag") {
+                        Some(tag) => {
+                            if let Some(current) = current.as_mut() {
+                                current.data = Some(Field::Data {
+                                    tag,
+                                    ind1: indicator(e, b"ind1"),
+                                    ind2: indicator(e, b"ind2"),
+                                    subfields: vec![],
+                                });
+                            }
+                        }
+                        None => set_err(&mut current, "datafield without tag"),
+                    },
+                    b"subfield" => match attr(e, b"code").and_then(|v| v.chars().next()) {
+                        Some(code) => target = Some(Target::Subfield(code)),
+                        None => set_err(&mut current, "subfield without code"),
+                    },
+                    _ => {}
+                }
+                if empty {
+                    if let Some(record) =
+                        end_element(e.local_name().as_ref(), &mut current, &mut target, &text)
+                    {
+                        records.push(record);
+                    }
+                }
+            }
+            Event::Text(e) if target.is_some() => {
+                let value = e.unescape().map_err(|e| format!("invalid xml: {e}"))?;
+                text.push_str(&value);
+            }
+            Event::CData(e) if target.is_some() => {
+                text.push_str(&String::from_utf8_lossy(&e));
+            }
+            Event::End(ref e) => {
+                if let Some(record) =
+                    end_element(e.local_name().as_ref(), &mut current, &mut target, &text)
+                {
+                    records.push(record);
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(records)
+}
+
+fn set_err(current: &mut Option<Current>, err: &str) {
+    if let Some(current) = current.as_mut() {
+        current.err.get_or_insert_with(|| err.to_string());
+    }
+}
+
+//元素结束时把文本写入记录，record 结束时返回完整的记录
+fn end_element(
+    name: &[u8],
+    current: &mut Option<Current>,
+    target: &mut Option<Target>,
+    text: &str,
+) -> Option<Result<Record, String>> {
+    if name == b"record" {
+        let current = current.take()?;
+        return Some(match current.err {
+            Some(err) => Err(err),
+            None => Ok(current.record),
+        });
+    }
+
+    let current = current.as_mut()?;
+    match (name, target.take()) {
+        (b"leader", Some(Target::Leader)) => current.record.leader = text.to_string(),
+        (b"controlfield", Some(Target::Control(tag))) => current.record.push(Field::Control {
+            tag,
+            value: text.to_string(),
+        }),
+        (b"subfield", Some(Target::Subfield(code))) => {
+            if let Some(Field::Data { subfields, .. }) = current.data.as_mut() {
+                subfields.push(Subfield {
+                    code,
+                    value: text.to_string(),
+                });
+            }
+        }
+        (b"datafield", _) => {
+            if let Some(field) = current.data.take() {
+                current.record.push(field);
+            }
+        }
+        (_, t) => *target = t,
+    }
+
+    None
+}
+
+pub fn write(records: &[Record]) -> Result<Vec<u8>, quick_xml::Error> {
+    let mut writer = Writer::new_with_indent(vec![], b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.write_event(Event::Start(
+        BytesStart::new("collection").with_attributes([("xmlns", NAMESPACE)]),
+    ))?;
+
+    for record in records {
+        writer.write_event(Event::Start(BytesStart::new("record")))?;
+        write_text(&mut writer, BytesStart::new("leader"), &record.leader)?;
+        for field in &record.fields {
+            match field {
+                Field::Control { tag, value } => {
+                    let start =
+                        BytesStart::new("controlfield").with_attributes([("tag", tag.as_str())]);
+                    write_text(&mut writer, start, value)?;
+                }
+                Field::Data {
+                    tag,
+                    ind1,
+                    ind2,
+                    subfields,
+                } => {
+                    let (ind1, ind2) = (ind1.to_string(), ind2.to_string());
+                    writer.write_event(Event::Start(
+                        BytesStart::new("datafield").with_attributes([
+                            ("tag", tag.as_str()),
+                            ("ind1", ind1.as_str()),
+                            ("ind2", ind2.as_str()),
+                        ]),
+                    ))?;
+                    for subfield in subfields {
+                        let code = subfield.code.to_string();
+                        let start =
+                            BytesStart::new("subfield").with_attributes([("code", code.as_str())]);
+                        write_text(&mut writer, start, &subfield.value)?;
+                    }
+                    writer.write_event(Event::End(BytesEnd::new("datafield")))?;
+                }
+            }
+        }
+        writer.write_event(Event::End(BytesEnd::new("record")))?;
+    }
+
+    writer.write_event(Event::End(BytesEnd::new("collection")))?;
+    Ok(writer.into_inner())
+}
+
+fn write_text(
+    writer: &mut Writer<Vec<u8>>,
+    start: BytesStart,
+    text: &str,
+) -> Result<(), quick_xml::Error> {
+    let end = BytesEnd::new(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+    writer.write_event(Event::Start(start))?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(end))?;
+    Ok(())
+}
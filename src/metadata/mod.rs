@@ -0,0 +1,126 @@
+//根据 ISBN 查询书目信息，用于添加书籍时预填表单
+pub mod open_library;
+
+use crate::config::current;
+use crate::db::metadata::{query as query_cache, save as save_cache, MetadataCache};
+use crate::db::record::now_with_timezone;
+use crate::error::Error;
+use crate::types::{
+    Author, Bookname, ContributorRole, Description, Isbn, Language, PageCount, Press,
+    PublicationYear, Subject,
+};
+use crate::CONFIG;
+use async_trait::async_trait;
+use chrono::Duration;
+use log::{debug, warn};
+use open_library::OpenLibrary;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContributorMetadata {
+    pub name: Author,
+    pub role: ContributorRole,
+}
+
+//字段名与 AddBookReq 一致，数据源没有提供的字段为空
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookMetadata {
+    pub name: Option<Bookname>,
+    pub author: Option<Author>,
+    pub press: Option<Press>,
+    pub publication_year: Option<PublicationYear>,
+    pub language: Option<Language>,
+    pub page_count: Option<PageCount>,
+    pub description: Option<Description>,
+    pub contributors: Vec<ContributorMetadata>,
+    pub subjects: Vec<Subject>,
+}
+
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    //数据源中没有该 ISBN 时返回 Ok(None)
+    async fn lookup(&self, isbn: &Isbn) -> Result<Option<BookMetadata>, Error>;
+}
+
+lazy_static::lazy_static! {
+    static ref PROVIDER: Box<dyn MetadataProvider> = Box::new(OpenLibrary::new(&CONFIG.metadata));
+}
+
+#[derive(Debug)]
+pub struct Lookup {
+    pub metadata: BookMetadata,
+    //是否来自数据库缓存
+    pub cached: bool,
+}
+
+fn decode(cache: &MetadataCache) -> Result<Option<BookMetadata>, Error> {
+    cache
+        .data
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|e| {
+            debug!("{e}");
+            Error::InternalErr
+        })
+}
+
+//数据源稍后可能收录新书，没有查到的结果最多缓存一天
+const NOT_FOUND_CACHE_DAYS: u32 = 1;
+
+//优先使用未过期的缓存；数据源不可用时退回到过期的缓存
+pub async fn lookup(isbn: &Isbn) -> Result<Lookup, Error> {
+    let cache = query_cache(isbn).await;
+    if let Some(cache) = cache.as_ref() {
+        let mut days = current().metadata.cache_days;
+        if cache.data.is_none() {
+            days = days.min(NOT_FOUND_CACHE_DAYS);
+        }
+        let expire = Duration::days(days as i64);
+        if cache.fetched_date + expire > now_with_timezone() {
+            let metadata = decode(cache)?.ok_or(Error::MetadataNotFound)?;
+            return Ok(Lookup {
+                metadata,
+                cached: true,
+            });
+        }
+    }
+
+    let metadata = match PROVIDER.lookup(isbn).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            let stale = cache.as_ref().map(decode).transpose()?.flatten();
+            return stale
+                .map(|metadata| Lookup {
+                    metadata,
+                    cached: true,
+                })
+                .ok_or(e);
+        }
+    };
+
+    let data = metadata
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| {
+            debug!("{e}");
+            Error::InternalErr
+        })?;
+    let cache = MetadataCache {
+        isbn: isbn.clone(),
+        data,
+        fetched_date: now_with_timezone(),
+    };
+    //缓存写入失败不影响本次查询
+    if let Err(e) = save_cache(&cache).await {
+        warn!("failed to cache metadata of {}: {e}", isbn.as_str());
+    }
+
+    metadata
+        .map(|metadata| Lookup {
+            metadata,
+            cached: false,
+        })
+        .ok_or(Error::MetadataNotFound)
+}
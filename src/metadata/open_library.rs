@@ -0,0 +1,265 @@
+use super::{BookMetadata, ContributorMetadata, MetadataProvider};
+use crate::config::Metadata as MetadataConfig;
+use crate::error::Error;
+use crate::types::{
+    Author, Bookname, ContributorRole, Description, Isbn, PageCount, Press, PublicationYear,
+    Subject,
+};
+use async_trait::async_trait;
+use log::debug;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+//主题分类过多时只保留前几项
+const MAX_SUBJECTS: usize = 10;
+//与 types 中各字段的校验上限一致，预填的内容可以直接提交
+const MAX_SUBJECT_LEN: usize = 50;
+const MAX_NAME_LEN: usize = 50;
+const MAX_AUTHOR_LEN: usize = 100;
+const MAX_PRESS_LEN: usize = 100;
+const MAX_DESCRIPTION_LEN: usize = 2000;
+
+//Open Library Books API：GET {base_url}/api/books?bibkeys=ISBN:xxx&format=json&jscmd=data
+pub struct OpenLibrary {
+    client: Client,
+    base_url: String,
+}
+
+impl OpenLibrary {
+    pub fn new(config: &MetadataConfig) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("failed to build http client");
+        Self {
+            client,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    title: Option<String>,
+    subtitle: Option<String>,
+    #[serde(default)]
+    authors: Vec<Named>,
+    #[serde(default)]
+    publishers: Vec<Named>,
+    publish_date: Option<String>,
+    number_of_pages: Option<u32>,
+    #[serde(default)]
+    subjects: Vec<Named>,
+    //可能是字符串，也可能是 {"type": ..., "value": ...}
+    notes: Option<serde_json::Value>,
+}
+
+//出版日期的格式不固定，如 `2007`、`March 2007`、`2007-03-01`，取其中第一个四位数
+fn parse_year(date: &str) -> Option<PublicationYear> {
+    date.split(|c: char| !c.is_ascii_digit())
+        .find(|s| s.len() == 4)
+        .and_then(|s| s.parse::<u32>().ok())
+        .map(PublicationYear::from)
+}
+
+//按字符数截断，去掉首尾空白后为空时视为没有
+fn clamp(s: &str, max: usize) -> Option<&str> {
+    let s = s.trim();
+    let end = s.char_indices().nth(max).map_or(s.len(), |(i, _)| i);
+    Some(s[..end].trim_end()).filter(|s| !s.is_empty())
+}
+
+impl From<Entry> for BookMetadata {
+    fn from(entry: Entry) -> Self {
+        let name = match (entry.title, entry.subtitle) {
+            (Some(title), Some(subtitle)) => Some(format!("{title}: {subtitle}")),
+            (title, _) => title,
+        };
+        let contributors: Vec<ContributorMetadata> = entry
+            .authors
+            .iter()
+            .filter_map(|author| clamp(&author.name, MAX_AUTHOR_LEN))
+            .map(|name| ContributorMetadata {
+                name: Author::from(name),
+                role: ContributorRole::Author,
+            })
+            .collect();
+        let description = entry.notes.and_then(|notes| match notes {
+            serde_json::Value::String(s) => Some(s),
+            notes => notes["value"].as_str().map(str::to_string),
+        });
+
+        Self {
+            name: name
+                .as_deref()
+                .and_then(|name| clamp(name, MAX_NAME_LEN))
+                .map(Bookname::from),
+            author: contributors.first().map(|c| c.name.clone()),
+            press: entry
+                .publishers
+                .first()
+                .and_then(|p| clamp(&p.name, MAX_PRESS_LEN))
+                .map(Press::from),
+            publication_year: entry.publish_date.as_deref().and_then(parse_year),
+            language: None,
+            page_count: entry.number_of_pages.map(PageCount::from),
+            description: description
+                .as_deref()
+                .and_then(|d| clamp(d, MAX_DESCRIPTION_LEN))
+                .map(Description::from),
+            contributors,
+            subjects: entry
+                .subjects
+                .iter()
+                .filter(|s| s.name.chars().count() <= MAX_SUBJECT_LEN)
+                .filter_map(|s| clamp(&s.name, MAX_SUBJECT_LEN))
+                .take(MAX_SUBJECTS)
+                .map(Subject::from)
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for OpenLibrary {
+    async fn lookup(&self, isbn: &Isbn) -> Result<Option<BookMetadata>, Error> {
+        let key = format!("ISBN:{}", isbn.as_str());
+        let resp = self
+            .client
+            .get(format!("{}/api/books", self.base_url))
+            .query(&[
+                ("bibkeys", key.as_str()),
+                ("format", "json"),
+                ("jscmd", "data"),
+            ])
+            .send()
+            .await
+            .map_err(|e| {
+                debug!("{e}");
+                Error::MetadataProviderErr
+            })?;
+
+        match resp.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => {
+                debug!("metadata provider responded {status}");
+                return Err(Error::MetadataProviderErr);
+            }
+            _ => {}
+        }
+
+        let mut entries: HashMap<String, Entry> = resp.json().await.map_err(|e| {
+            debug!("{e}");
+            Error::MetadataProviderErr
+        })?;
+        Ok(entries.remove(&key).map(BookMetadata::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const FOUND: &str = "9787536692930";
+    const MISSING: &str = "9780306406157";
+    const BROKEN: &str = "9780198534532";
+
+    //按请求中的 ISBN 返回固定响应的本地 HTTP 服务
+    async fn stub() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let (status, body) = if request.contains(FOUND) {
+                    let entry = json!({
+                        "title": "三体",
+                        "subtitle": "地球往事",
+                        "authors": [{ "name": "刘慈欣" }, { "name": "  " }],
+                        "publishers": [{ "name": "重庆出版社" }],
+                        "publish_date": "January 2008",
+                        "number_of_pages": 302,
+                        "subjects": [{ "name": "Science fiction" }, { "name": "x".repeat(51) }],
+                        "notes": { "type": "/type/text", "value": "长".repeat(2100) },
+                    });
+                    (
+                        "200 OK",
+                        json!({ format!("ISBN:{FOUND}"): entry }).to_string(),
+                    )
+                } else if request.contains(MISSING) {
+                    ("200 OK", "{}".to_string())
+                } else {
+                    ("500 Internal Server Error", String::new())
+                };
+                let resp = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(resp.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{addr}/")
+    }
+
+    async fn provider() -> OpenLibrary {
+        OpenLibrary::new(&MetadataConfig {
+            base_url: stub().await,
+            timeout: 5,
+            cache_days: 30,
+        })
+    }
+
+    #[tokio::test]
+    async fn lookup_found() {
+        let metadata = provider()
+            .await
+            .lookup(&FOUND.parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&metadata).unwrap(),
+            json!({
+                "name": "三体: 地球往事",
+                "author": "刘慈欣",
+                "press": "重庆出版社",
+                "publication_year": 2008,
+                "language": null,
+                "page_count": 302,
+                "description": "长".repeat(MAX_DESCRIPTION_LEN),
+                "contributors": [{ "name": "刘慈欣", "role": "author" }],
+                "subjects": ["Science fiction"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_missing_and_failed() {
+        let provider = provider().await;
+        let missing = provider.lookup(&MISSING.parse().unwrap()).await.unwrap();
+        assert!(missing.is_none());
+        let err = provider.lookup(&BROKEN.parse().unwrap()).await.unwrap_err();
+        assert!(matches!(err, Error::MetadataProviderErr));
+    }
+
+    #[test]
+    fn clamp_to_limits() {
+        assert_eq!(clamp("  三体  ", 50), Some("三体"));
+        assert_eq!(clamp("三体：地球往事", 2), Some("三体"));
+        assert_eq!(clamp("ab cd", 3), Some("ab"));
+        assert_eq!(clamp("   ", 10), None);
+    }
+}
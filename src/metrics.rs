@@ -0,0 +1,151 @@
+//Prometheus 指标：请求数与耗时由 middleware::metrics 记录，数据库耗时由 DbTimer 记录，
+//借阅、馆藏和用户数量由 refresh 定期更新
+use crate::db::book::holdings;
+use crate::db::record::loan_totals;
+use crate::db::user::count as count_users;
+use crate::error::Error;
+use crate::CONFIG;
+use log::{debug, error, info, trace, warn, LevelFilter};
+use poem::http::StatusCode;
+use poem::{handler, IntoResponse, Response};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use rbatis::plugin::log::LogPlugin;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Registry = Registry::new_custom(Some("library".into()), None).unwrap();
+
+    pub static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests by route, status and business code"),
+        &["method", "route", "status", "code"],
+    ));
+    pub static ref HTTP_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+        &["method", "route"],
+    ));
+    static ref DB_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("db_query_duration_seconds", "Database query latency")
+            .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+        &["kind", "result"],
+    ));
+
+    static ref ACTIVE_LOANS: IntGauge = register(IntGauge::new("active_loans", "Books currently on loan"));
+    static ref OVERDUE_LOANS: IntGauge = register(IntGauge::new("overdue_loans", "Loans past their due date"));
+    static ref RETURNED_LOANS: IntGauge = register(IntGauge::new("returned_loans", "Returned loans"));
+    static ref BOOK_TITLES: IntGauge = register(IntGauge::new("book_titles", "Book titles not withdrawn"));
+    static ref BOOK_COPIES: IntGauge = register(IntGauge::new("book_copies", "Copies of books not withdrawn"));
+    static ref USERS: IntGauge = register(IntGauge::new("users", "Registered users"));
+}
+
+fn register<T, E>(metric: Result<T, E>) -> T
+where
+    T: Collector + Clone + 'static,
+    E: std::fmt::Debug,
+{
+    let metric = metric.unwrap();
+    REGISTRY.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+async fn refresh() -> Result<(), Error> {
+    let loans = loan_totals().await?;
+    ACTIVE_LOANS.set(loans.active as i64);
+    OVERDUE_LOANS.set(loans.overdue as i64);
+    RETURNED_LOANS.set(loans.returned as i64);
+
+    let books = holdings().await?;
+    BOOK_TITLES.set(books.titles as i64);
+    BOOK_COPIES.set(books.copies as i64);
+
+    USERS.set(count_users().await? as i64);
+    Ok(())
+}
+
+//定期更新统计类指标
+pub fn spawn_refresh() {
+    let period = Duration::from_secs(CONFIG.metrics.refresh_interval.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(e) = refresh().await {
+                warn!("failed to refresh metrics: {e}");
+            }
+        }
+    });
+}
+
+#[handler]
+pub fn export() -> Response {
+    let encoder = TextEncoder::new();
+    let mut buf = vec![];
+    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buf) {
+        debug!("{e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    buf.with_content_type(encoder.format_type()).into_response()
+}
+
+//rbatis 在执行 SQL 前后以同一个 id 调用日志插件，借此统计耗时；日志照常输出到 rbatis target
+#[derive(Debug, Default)]
+pub struct DbTimer {
+    started: Mutex<HashMap<i64, (&'static str, Instant)>>,
+}
+
+impl DbTimer {
+    fn start(&self, id: i64, kind: &'static str) {
+        self.started
+            .lock()
+            .unwrap()
+            .insert(id, (kind, Instant::now()));
+    }
+
+    fn observe(&self, id: i64, ok: bool) {
+        if let Some((kind, start)) = self.started.lock().unwrap().remove(&id) {
+            DB_DURATION
+                .with_label_values(&[kind, if ok { "ok" } else { "error" }])
+                .observe(start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+impl LogPlugin for DbTimer {
+    //计时依赖 info 级别的 Exec/Fetch 和 RowsAffected/ReturnRows 回调，所以 rbatis 端不能过滤；
+    //代价是每条 SQL 都会格式化参数和结果，关闭的级别仍由 tracing 的过滤规则丢弃
+    fn get_level_filter(&self) -> &LevelFilter {
+        &LevelFilter::Trace
+    }
+
+    fn error(&self, id: i64, data: &str) {
+        self.observe(id, false);
+        error!(target: "rbatis", "[rbatis] [{id}] {data}");
+    }
+
+    fn warn(&self, id: i64, data: &str) {
+        warn!(target: "rbatis", "[rbatis] [{id}] {data}");
+    }
+
+    fn info(&self, id: i64, data: &str) {
+        if data.starts_with("Exec") {
+            self.start(id, "exec");
+        } else if data.starts_with("Fetch") {
+            self.start(id, "fetch");
+        } else if data.starts_with("RowsAffected") || data.starts_with("ReturnRows") {
+            self.observe(id, true);
+        }
+        info!(target: "rbatis", "[rbatis] [{id}] {data}");
+    }
+
+    fn debug(&self, id: i64, data: &str) {
+        debug!(target: "rbatis", "[rbatis] [{id}] {data}");
+    }
+
+    fn trace(&self, id: i64, data: &str) {
+        trace!(target: "rbatis", "[rbatis] [{id}] {data}");
+    }
+}
@@ -0,0 +1,38 @@
+use poem::{Endpoint, IntoResponse, Request, Response, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+//未返回响应就被丢弃的请求数，如因关闭超时被取消；关闭期间新到的请求也照此统计，
+//不会掩盖被丢弃的请求
+static ABORTED: AtomicUsize = AtomicUsize::new(0);
+
+//请求处理完成或被丢弃时减一
+struct Guard {
+    completed: bool,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        if !self.completed {
+            ABORTED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+//正在处理的请求数，关闭服务时用于统计
+pub fn in_flight_requests() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+pub fn aborted_requests() -> usize {
+    ABORTED.load(Ordering::SeqCst)
+}
+
+pub async fn in_flight<E: Endpoint>(next: E, req: Request) -> Result<Response> {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    let mut guard = Guard { completed: false };
+    let resp = next.call(req).await.map(IntoResponse::into_response);
+    guard.completed = true;
+    resp
+}
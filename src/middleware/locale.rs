@@ -0,0 +1,22 @@
+use crate::i18n::{self, Locale};
+use poem::http::header::ACCEPT_LANGUAGE;
+use poem::{Endpoint, IntoResponse, Request, Response, Result};
+
+//按 Accept-Language 选择本次请求的语言，登录用户的语言设置由 token 中间件处理；
+//须放在最外层，使内层中间件把错误转换为响应时也使用该语言
+pub async fn locale<E: Endpoint>(next: E, req: Request) -> Result<Response> {
+    let locale = req
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Locale::negotiate)
+        .unwrap_or_default();
+
+    i18n::scope(locale, async move {
+        Ok(match next.call(req).await {
+            Ok(resp) => resp.into_response(),
+            Err(err) => err.into_response(),
+        })
+    })
+    .await
+}
@@ -1,57 +1,279 @@
+use crate::auth::Token;
+use crate::config::{parse_timezone, LogFormat};
+use crate::error::SUCCESS_CODE;
 use crate::CONFIG;
 use colored::Colorize;
-use log::{info, warn};
+use poem::http::header::HeaderValue;
 use poem::{Endpoint, IntoResponse, Request, Response, Result};
-use time::{format_description, UtcOffset};
+use rand::Rng;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Instant;
+use time::format_description::{self, FormatItem};
+use time::{OffsetDateTime, UtcOffset};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::time::OffsetTime;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
-pub fn init_log(level: &str) {
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+//只从不超过该长度的 JSON 响应中读取业务状态码
+const MAX_CODE_BODY_LEN: usize = 64 * 1024;
+const TIME_FORMAT: &str = "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]";
+
+//本次请求的 ID，可在 handler 中通过 Data<&RequestId> 获取
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+//响应体中的业务状态码，写入响应的 extensions 供外层的 metrics 使用
+#[derive(Debug, Clone, Copy)]
+pub struct BusinessCode(pub u64);
+
+struct JsonFormat {
+    offset: UtcOffset,
+    time_format: Vec<FormatItem<'static>>,
+}
+
+#[derive(Default)]
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let time = OffsetDateTime::now_utc()
+            .to_offset(self.offset)
+            .format(&self.time_format)
+            .map_err(|_| fmt::Error)?;
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        let mut line = Map::new();
+        line.insert("time".into(), time.into());
+        line.insert("level".into(), meta.level().as_str().into());
+        //经由 log 宏输出的事件，target 等信息在 log. 开头的字段中
+        let target = visitor
+            .0
+            .remove("log.target")
+            .unwrap_or_else(|| meta.target().into());
+        line.insert("target".into(), target);
+        let file = visitor.0.remove("log.file");
+        if let Some(file) = file.or_else(|| meta.file().map(Value::from)) {
+            line.insert("file".into(), file);
+        }
+        let line_no = visitor.0.remove("log.line");
+        if let Some(line_no) = line_no.or_else(|| meta.line().map(Value::from)) {
+            line.insert("line".into(), line_no);
+        }
+        visitor.0.remove("log.module_path");
+        line.extend(visitor.0);
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+//设置了 RUST_LOG 时以其为准，否则使用 global.log 和 db.log
+fn build_filter(level: &str, db_log: Option<&str>) -> EnvFilter {
     let mut filter = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new(level))
         .unwrap()
         .add_directive("poem::server=warn".parse().unwrap())
         .add_directive("hyper::proto::h1=warn".parse().unwrap());
-    if let Some(level) = &CONFIG.db.log {
+    if let Some(level) = db_log {
         filter = filter.add_directive(format!("rbatis={level}").parse().unwrap());
     }
-    let local_time = OffsetTime::new(
-        UtcOffset::from_hms(8, 0, 0).unwrap(),
-        format_description::parse(
-            "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]",
-        )
-        .unwrap(),
-    );
-    let fmt = tracing_subscriber::fmt::layer()
-        .with_target(false)
-        .with_file(true)
-        .with_line_number(true)
-        .with_timer(local_time);
-    tracing_subscriber::registry().with(filter).with(fmt).init();
-}
-
-pub async fn log<E: Endpoint>(next: E, req: Request) -> Result<Response> {
-    let uri = String::from(req.uri().path());
-    let method = req.method().clone();
-    let method = if uri.contains("prod-api") {
-        method.to_string().blue().to_string()
-    } else {
-        method.to_string()
+    filter
+}
+
+//log 宏在转发给 tracing 前先按 log::max_level 过滤，需随过滤规则一起更新
+fn sync_log_max_level() {
+    let level = match LevelFilter::current().into_level() {
+        None => log::LevelFilter::Off,
+        Some(Level::ERROR) => log::LevelFilter::Error,
+        Some(Level::WARN) => log::LevelFilter::Warn,
+        Some(Level::INFO) => log::LevelFilter::Info,
+        Some(Level::DEBUG) => log::LevelFilter::Debug,
+        Some(Level::TRACE) => log::LevelFilter::Trace,
     };
-    let res = next.call(req).await;
-
-    match res {
-        Ok(resp) => {
-            let resp = resp.into_response();
-            let stat = resp.status();
-            info!("[{stat}] {method} {uri}");
-            Ok(resp)
+    log::set_max_level(level);
+}
+
+//重新加载配置后替换日志过滤规则，日志格式和时区需要重启才能修改
+pub fn reload_log(level: &str, db_log: Option<&str>) {
+    if let Some(handle) = FILTER.get() {
+        if let Err(e) = handle.reload(build_filter(level, db_log)) {
+            tracing::warn!("failed to reload log filter: {e}");
         }
-        Err(err) => {
-            warn!("[{err}] {method} {uri}");
+        sync_log_max_level();
+    }
+}
+
+//服务的日志输出到标准输出；命令行工具输出到标准错误，以免混入导出的数据
+pub fn init_log<W>(level: &str, writer: W)
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let (filter, handle) = reload::Layer::new(build_filter(level, CONFIG.db.log.as_deref()));
+    let _ = FILTER.set(handle);
+    //已在加载配置时校验
+    let offset = parse_timezone(&CONFIG.global.log_timezone).unwrap();
+    let time_format = format_description::parse(TIME_FORMAT).unwrap();
 
-            Err(err)
+    match CONFIG.global.log_format {
+        LogFormat::Pretty => {
+            let fmt = tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_file(true)
+                .with_line_number(true)
+                .with_writer(writer)
+                .with_timer(OffsetTime::new(offset, time_format));
+            tracing_subscriber::registry().with(filter).with(fmt).init();
         }
+        LogFormat::Json => {
+            let fmt = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer)
+                .event_format(JsonFormat {
+                    offset,
+                    time_format,
+                });
+            tracing_subscriber::registry().with(filter).with(fmt).init();
+        }
+    }
+}
+
+//沿用请求中合法的 X-Request-Id，否则生成一个新的
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::thread_rng().gen::<u128>()))
+}
+
+//接口以 200 返回 {"code": ..} 形式的业务状态码，读取后重新放回响应体；
+//此时响应头中还没有 Content-Length，按读出的长度跳过导出文件等较大的响应
+async fn business_code(resp: Response) -> (Response, Option<u64>) {
+    //错误响应没有 Content-Type，静态文件等其它类型的响应不读取
+    let readable = resp
+        .content_type()
+        .is_none_or(|ty| ty.starts_with("application/json") || ty.starts_with("text/plain"));
+    if !readable {
+        return (resp, None);
+    }
+
+    let (parts, body) = resp.into_parts();
+    let body = match body.into_bytes().await {
+        Ok(body) => body,
+        Err(_) => return (Response::from_parts(parts, Default::default()), None),
+    };
+    let code = (body.len() <= MAX_CODE_BODY_LEN)
+        .then(|| serde_json::from_slice::<Value>(&body).ok())
+        .flatten()
+        .and_then(|v| v.get("code").and_then(Value::as_u64));
+    (Response::from_parts(parts, body.into()), code)
+}
+
+pub async fn log<E: Endpoint>(next: E, mut req: Request) -> Result<Response> {
+    let start = Instant::now();
+    let id = request_id(&req);
+    req.extensions_mut().insert(RequestId(id.clone()));
+    let path = String::from(req.uri().path());
+    let method = req.method().to_string();
+    let ip = req
+        .remote_addr()
+        .as_socket_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+
+    let resp = match next.call(req).await {
+        Ok(resp) => resp.into_response(),
+        Err(err) => err.into_response(),
+    };
+    let (mut resp, code) = business_code(resp).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    if let Some(code) = code {
+        resp.extensions_mut().insert(BusinessCode(code));
     }
+
+    let status = resp.status().as_u16();
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let user = resp
+        .extensions()
+        .get::<Token>()
+        .map(|token| token.email.as_str().to_string())
+        .unwrap_or_default();
+    let colored = if path.contains("prod-api") && CONFIG.global.log_format == LogFormat::Pretty {
+        method.blue().to_string()
+    } else {
+        method.clone()
+    };
+    let failed = status >= 500 || code.is_some_and(|code| code != SUCCESS_CODE as u64);
+    let code = code.unwrap_or_default();
+
+    if failed {
+        tracing::warn!(
+            request_id = %id, method = %method, path = %path, status, code, latency_ms,
+            user = %user, ip = %ip, "[{status}] {colored} {path}"
+        );
+    } else {
+        tracing::info!(
+            request_id = %id, method = %method, path = %path, status, code, latency_ms,
+            user = %user, ip = %ip, "[{status}] {colored} {path}"
+        );
+    }
+
+    Ok(resp)
 }
@@ -0,0 +1,53 @@
+use super::BusinessCode;
+use crate::metrics::{HTTP_DURATION, HTTP_REQUESTS};
+use crate::upload::UPLOAD_ROUTE;
+use poem::http::StatusCode;
+use poem::{Endpoint, IntoResponse, Request, Response, Result};
+use std::time::Instant;
+
+const API_PREFIX: &str = "/prod-api/";
+const PROBE_ROUTES: [&str; 3] = ["/metrics", "/healthz", "/readyz"];
+
+//接口路径均为固定值可直接作为标签；上传文件和前端静态资源合并，避免标签数量无限增长
+fn route(path: &str, status: StatusCode) -> String {
+    if path.starts_with(API_PREFIX) {
+        if status == StatusCode::NOT_FOUND {
+            "unmatched".to_string()
+        } else {
+            path.to_string()
+        }
+    } else if path.starts_with(UPLOAD_ROUTE) {
+        UPLOAD_ROUTE.to_string()
+    } else if PROBE_ROUTES.contains(&path) {
+        path.to_string()
+    } else {
+        "static".to_string()
+    }
+}
+
+pub async fn metrics<E: Endpoint>(next: E, req: Request) -> Result<Response> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+
+    let resp = match next.call(req).await {
+        Ok(resp) => resp.into_response(),
+        Err(err) => err.into_response(),
+    };
+
+    let status = resp.status();
+    let route = route(&path, status);
+    let code = resp
+        .extensions()
+        .get::<BusinessCode>()
+        .map(|code| code.0.to_string())
+        .unwrap_or_default();
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, status.as_str(), &code])
+        .inc();
+    HTTP_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+
+    Ok(resp)
+}
@@ -1,5 +1,13 @@
+mod in_flight;
+mod locale;
 mod logging;
+mod metrics;
+mod rate_limit;
 mod token;
 
-pub use logging::{init_log, log};
+pub use in_flight::{aborted_requests, in_flight, in_flight_requests};
+pub use locale::locale;
+pub use logging::{init_log, log, reload_log, BusinessCode, RequestId, REQUEST_ID_HEADER};
+pub use metrics::metrics;
+pub use rate_limit::{ip_rate_limit, rate_limit, spawn_bucket_sweeper};
 pub use token::token;
@@ -0,0 +1,236 @@
+//令牌桶限流：登录用户按邮箱计数，未登录的请求按 IP 计数，各组接口分别限制；
+//另有一层在校验 Token 之前按 IP 统计全部请求，避免用无效 Token 绕过限流
+use crate::auth::Token;
+use crate::config::{current, Limit};
+use crate::error::Error;
+use poem::error::ResponseError;
+use poem::http::header::RETRY_AFTER;
+use poem::{Endpoint, IntoResponse, Request, Response, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const API_PREFIX: &str = "/prod-api/";
+const AUTH_ROUTES: [&str; 2] = [
+    "/prod-api/books-manager/user/login",
+    "/prod-api/books-manager/user/register",
+];
+const SEARCH_ROUTES: [&str; 3] = [
+    "/prod-api/books-manager/book/search",
+    "/prod-api/books-manager/book/list",
+    "/prod-api/books-manager/admin/book/lookup",
+];
+//定期清理已经补满的桶，避免在请求路径上遍历
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Group {
+    Auth,
+    Search,
+    Api,
+    //同一 IP 的全部请求
+    Ip,
+}
+
+impl Group {
+    fn of(path: &str) -> Option<Group> {
+        if AUTH_ROUTES.contains(&path) {
+            Some(Group::Auth)
+        } else if SEARCH_ROUTES.contains(&path) {
+            Some(Group::Search)
+        } else if path.starts_with(API_PREFIX) {
+            Some(Group::Api)
+        } else {
+            None
+        }
+    }
+
+    fn limit(self) -> Limit {
+        let config = current();
+        match self {
+            Group::Auth => config.ratelimit.auth.clone(),
+            Group::Search => config.ratelimit.search.clone(),
+            Group::Api => config.ratelimit.api.clone(),
+            Group::Ip => config.ratelimit.ip.clone(),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    //按经过的时间补充令牌，不超过 burst
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let rate = limit.per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BUCKETS: Mutex<HashMap<(Group, String), Bucket>> = Mutex::new(HashMap::new());
+}
+
+impl Bucket {
+    fn full(limit: &Limit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    //取出一个令牌，令牌不足时返回需要等待的秒数
+    fn take(&mut self, limit: &Limit, now: Instant) -> Result<(), u64> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            //减去一个很小的值，避免浮点误差使整秒的等待时间多算一秒
+            let rate = limit.per_minute as f64 / 60.0;
+            Err(((1.0 - self.tokens) / rate - 1e-9).ceil() as u64)
+        }
+    }
+}
+
+fn acquire(group: Group, key: String) -> Result<(), u64> {
+    let limit = group.limit();
+    let now = Instant::now();
+    let mut buckets = BUCKETS.lock().unwrap();
+    buckets
+        .entry((group, key))
+        .or_insert_with(|| Bucket::full(&limit, now))
+        .take(&limit, now)
+}
+
+//空闲到补满的桶与新建的桶没有区别，可以直接删除
+fn sweep() {
+    let now = Instant::now();
+    BUCKETS.lock().unwrap().retain(|(group, _), bucket| {
+        let limit = group.limit();
+        bucket.refill(&limit, now);
+        bucket.tokens < limit.burst as f64
+    });
+}
+
+pub fn spawn_bucket_sweeper() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweep();
+        }
+    });
+}
+
+fn ip_key(req: &Request) -> String {
+    let ip = req
+        .remote_addr()
+        .as_socket_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    format!("ip:{ip}")
+}
+
+fn client_key(req: &Request) -> String {
+    match req.extensions().get::<Token>() {
+        Some(token) => format!("user:{}", token.email.as_str()),
+        None => ip_key(req),
+    }
+}
+
+async fn limit<E: Endpoint>(next: E, req: Request, group: Group, key: String) -> Result<Response> {
+    match acquire(group, key) {
+        Ok(()) => next.call(req).await.map(IntoResponse::into_response),
+        Err(retry_after) => Ok(Error::TooManyRequests
+            .as_response()
+            .with_header(RETRY_AFTER, retry_after.max(1))
+            .into_response()),
+    }
+}
+
+//须放在 token 中间件内层，才能取到登录用户
+pub async fn rate_limit<E: Endpoint>(next: E, req: Request) -> Result<Response> {
+    match Group::of(req.uri().path()) {
+        Some(group) if current().ratelimit.enabled => {
+            let key = client_key(&req);
+            limit(next, req, group, key).await
+        }
+        _ => next.call(req).await.map(IntoResponse::into_response),
+    }
+}
+
+//须放在 token 中间件外层，Token 无效而被拒绝的请求同样计数
+pub async fn ip_rate_limit<E: Endpoint>(next: E, req: Request) -> Result<Response> {
+    if current().ratelimit.enabled && req.uri().path().starts_with(API_PREFIX) {
+        let key = ip_key(&req);
+        limit(next, req, Group::Ip, key).await
+    } else {
+        next.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_and_refill() {
+        let limit = Limit {
+            per_minute: 60,
+            burst: 3,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::full(&limit, start);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(&limit, start), Ok(()));
+        }
+        assert_eq!(bucket.take(&limit, start), Err(1));
+
+        //每秒补充一个令牌
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(bucket.take(&limit, later), Ok(()));
+        assert_eq!(bucket.take(&limit, later), Err(1));
+
+        //补充不超过 burst
+        let idle = later + Duration::from_secs(600);
+        bucket.refill(&limit, idle);
+        assert_eq!(bucket.tokens, 3.0);
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        let limit = Limit {
+            per_minute: 10,
+            burst: 1,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::full(&limit, start);
+        assert_eq!(bucket.take(&limit, start), Ok(()));
+        //每 6 秒补充一个令牌
+        assert_eq!(bucket.take(&limit, start), Err(6));
+        assert_eq!(bucket.take(&limit, start + Duration::from_secs(4)), Err(2));
+        assert_eq!(bucket.take(&limit, start + Duration::from_secs(6)), Ok(()));
+    }
+
+    #[test]
+    fn group_of_path() {
+        assert_eq!(
+            Group::of("/prod-api/books-manager/user/login"),
+            Some(Group::Auth)
+        );
+        assert_eq!(
+            Group::of("/prod-api/books-manager/book/search"),
+            Some(Group::Search)
+        );
+        assert_eq!(
+            Group::of("/prod-api/books-manager/admin/loan/list"),
+            Some(Group::Api)
+        );
+        assert_eq!(Group::of("/health"), None);
+    }
+}
@@ -1,17 +1,29 @@
-use crate::auth::{verify_token, Token};
-use crate::db::user::query;
+use crate::auth::verify_token;
+use crate::db::user::{query, User};
 use crate::error::Error;
+use crate::i18n;
 use crate::types::Status;
 use log::debug;
+use poem::error::ResponseError;
 use poem::{Endpoint, IntoResponse, Request, Response, Result};
 
 const TOKEN_HEADER: &str = "X-Token";
+//须修改密码的用户只能访问这些接口
+const PASSWORD_CHANGE_ROUTES: [&str; 4] = [
+    "/prod-api/books-manager/user/change_password",
+    "/prod-api/books-manager/user/locale",
+    "/prod-api/books-manager/user/info",
+    "/prod-api/books-manager/user/logout",
+];
 
-async fn is_user_enabled(token: &Token) -> bool {
-    query(&token.email)
-        .await
-        .and_then(|user| user.status.ne(&Status::Disabled).then(|| ()))
-        .is_some()
+fn check_user(user: Option<&User>, path: &str) -> Result<(), Error> {
+    let user = user
+        .filter(|user| user.status != Status::Disabled)
+        .ok_or(Error::AccountWasDisabled)?;
+    if user.must_change_password && !PASSWORD_CHANGE_ROUTES.contains(&path) {
+        return Err(Error::PasswordChangeRequired);
+    }
+    Ok(())
 }
 
 pub async fn token<E: Endpoint>(next: E, mut req: Request) -> Result<Response> {
@@ -24,13 +36,28 @@ pub async fn token<E: Endpoint>(next: E, mut req: Request) -> Result<Response> {
         debug!("Token: {token:?}");
 
         if let Some(token) = token {
-            if is_user_enabled(&token).await {
-                req.extensions_mut().insert(token);
-            } else {
-                return Ok(Error::AccountWasDisabled.to_string().into_response());
-            }
+            let user = query(&token.email).await;
+            //用户设置的语言优先于 Accept-Language
+            let locale = user
+                .as_ref()
+                .and_then(|user| user.locale)
+                .unwrap_or_else(i18n::current);
+            return i18n::scope(locale, async move {
+                if let Err(e) = check_user(user.as_ref(), req.uri().path()) {
+                    return Ok(e.as_response());
+                }
+                req.extensions_mut().insert(token.clone());
+                //供外层的访问日志记录当前用户
+                let mut resp = match next.call(req).await {
+                    Ok(resp) => resp.into_response(),
+                    Err(err) => err.into_response(),
+                };
+                resp.extensions_mut().insert(token);
+                Ok(resp)
+            })
+            .await;
         } else {
-            return Ok(Error::InvalidlToken.to_string().into_response());
+            return Ok(Error::InvalidlToken.as_response());
         }
     }
 
@@ -0,0 +1,55 @@
+//收到 SIGHUP 或配置文件被修改时重新加载配置，只替换可热加载的部分
+use crate::config::{self, current};
+use crate::middleware::reload_log;
+use crate::tls;
+use crate::CONFIG;
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+fn apply() {
+    match config::reload() {
+        Ok(restart_required) => {
+            let config = current();
+            reload_log(&config.global.log, config.db.log.as_deref());
+            for key in restart_required {
+                warn!("`{key}` has changed, restart the server to apply it");
+            }
+            info!("config reloaded");
+        }
+        Err(e) => error!("failed to reload config, keeping the previous one: {e}"),
+    }
+}
+
+pub fn spawn_watcher() {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("failed to listen for SIGHUP: {e}");
+            return;
+        }
+    };
+    let watch = CONFIG.global.reload_interval > 0;
+    let period = Duration::from_secs(CONFIG.global.reload_interval.max(1));
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut modified = config::modified();
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    info!("received SIGHUP, reloading config");
+                    tls::reload();
+                }
+                _ = interval.tick(), if watch => {
+                    if config::modified() == modified {
+                        continue;
+                    }
+                    info!("config file has changed, reloading");
+                }
+            }
+            modified = config::modified();
+            apply();
+        }
+    });
+}
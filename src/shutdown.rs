@@ -0,0 +1,72 @@
+//收到 SIGTERM 或 Ctrl-C 后停止接受新连接，等待处理中的请求完成后关闭数据库连接池
+use crate::db;
+use crate::middleware::{aborted_requests, in_flight_requests};
+use log::{info, warn};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+lazy_static::lazy_static! {
+    static ref SHUTDOWN: (watch::Sender<bool>, watch::Receiver<bool>) = watch::channel(false);
+}
+
+struct Drain {
+    start: Instant,
+    in_flight: usize,
+    aborted: usize,
+}
+
+//开始关闭时的状态，用于输出汇总
+static DRAIN: OnceLock<Drain> = OnceLock::new();
+
+pub fn spawn_listener() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("failed to listen for SIGTERM: {e}");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => info!("received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("received Ctrl-C, shutting down"),
+        }
+        let in_flight = in_flight_requests();
+        let _ = DRAIN.set(Drain {
+            start: Instant::now(),
+            in_flight,
+            aborted: aborted_requests(),
+        });
+        info!("stop accepting connections, waiting for {in_flight} in-flight requests");
+        let _ = SHUTDOWN.0.send(true);
+    });
+}
+
+//各个 Server 在该 future 完成后开始关闭
+pub async fn requested() {
+    let mut rx = SHUTDOWN.1.clone();
+    while !*rx.borrow() {
+        if rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+//所有 Server 退出后调用：输出汇总并关闭数据库连接池
+pub async fn finish() {
+    if let Some(drain) = DRAIN.get() {
+        let in_flight = drain.in_flight;
+        let aborted = aborted_requests() - drain.aborted;
+        let elapsed = drain.start.elapsed().as_millis();
+        if aborted > 0 {
+            warn!("shutdown timed out after {elapsed} ms, {aborted} requests were aborted");
+        } else {
+            info!("drained {in_flight} in-flight requests in {elapsed} ms");
+        }
+    }
+    db::close().await;
+    info!("shutdown complete");
+}
@@ -0,0 +1,219 @@
+//可选的 HTTPS：证书文件修改或收到 SIGHUP 时重新加载，另可监听 HTTP 地址重定向到 HTTPS
+use crate::CONFIG;
+use futures_util::stream::{self, Stream, StreamExt};
+use log::{error, info};
+use poem::http::header::{HOST, STRICT_TRANSPORT_SECURITY};
+use poem::http::StatusCode;
+use poem::listener::{Listener, RustlsCertificate, RustlsConfig, TcpListener};
+use poem::middleware::SetHeader;
+use poem::web::Redirect;
+use poem::{handler, IntoResponse, Request, Response};
+use std::fs;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio::time::Interval;
+
+lazy_static::lazy_static! {
+    static ref RELOAD: Notify = Notify::new();
+}
+
+pub fn enabled() -> bool {
+    CONFIG.global.tls_cert.is_some() && CONFIG.global.tls_key.is_some()
+}
+
+//立即重新读取证书，由 SIGHUP 触发
+pub fn reload() {
+    if enabled() {
+        RELOAD.notify_one();
+    }
+}
+
+fn invalid(path: &Path, message: &str) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidData,
+        format!("{}: {message}", path.display()),
+    )
+}
+
+//与 poem 一致，只支持 PKCS#8 和 RSA 私钥
+fn read(cert: &Path, key: &Path) -> IoResult<RustlsConfig> {
+    let cert_pem = fs::read(cert).map_err(|e| invalid(cert, &e.to_string()))?;
+    let key_pem = fs::read(key).map_err(|e| invalid(key, &e.to_string()))?;
+
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice()).unwrap_or_default();
+    if certs.is_empty() {
+        return Err(invalid(cert, "no certificate found"));
+    }
+    let has_key = !rustls_pemfile::pkcs8_private_keys(&mut key_pem.as_slice())
+        .unwrap_or_default()
+        .is_empty()
+        || !rustls_pemfile::rsa_private_keys(&mut key_pem.as_slice())
+            .unwrap_or_default()
+            .is_empty();
+    if !has_key {
+        return Err(invalid(key, "no PKCS#8 or RSA private key found"));
+    }
+
+    Ok(RustlsConfig::new().fallback(RustlsCertificate::new().cert(cert_pem).key(key_pem)))
+}
+
+fn modified(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    Some((modified(cert)?, modified(key)?))
+}
+
+struct Watch {
+    cert: PathBuf,
+    key: PathBuf,
+    modified: Option<(SystemTime, SystemTime)>,
+    interval: Option<Interval>,
+}
+
+impl Watch {
+    async fn tick(&mut self) {
+        match &mut self.interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    //等待证书被修改或收到重新加载的通知，新证书无效时继续使用原证书
+    async fn next(mut self) -> Option<(RustlsConfig, Self)> {
+        loop {
+            tokio::select! {
+                _ = RELOAD.notified() => {}
+                _ = self.tick() => {
+                    if modified(&self.cert, &self.key) == self.modified {
+                        continue;
+                    }
+                }
+            }
+            self.modified = modified(&self.cert, &self.key);
+            match read(&self.cert, &self.key) {
+                Ok(config) => {
+                    info!("reloaded tls certificate {}", self.cert.display());
+                    return Some((config, self));
+                }
+                Err(e) => error!("failed to reload tls certificate, keeping the previous one: {e}"),
+            }
+        }
+    }
+}
+
+fn config_stream(cert: &str, key: &str) -> IoResult<impl Stream<Item = RustlsConfig>> {
+    let (cert, key) = (PathBuf::from(cert), PathBuf::from(key));
+    let first = read(&cert, &key)?;
+    let period = CONFIG.global.reload_interval;
+    let watch = Watch {
+        modified: modified(&cert, &key),
+        cert,
+        key,
+        interval: (period > 0).then(|| tokio::time::interval(Duration::from_secs(period))),
+    };
+    Ok(stream::once(async { first }).chain(stream::unfold(watch, Watch::next)))
+}
+
+//启用 TLS 时包装为 HTTPS 监听，证书无效时启动失败
+pub fn bind(addr: &'static str) -> IoResult<poem::listener::BoxListener> {
+    let listener = TcpListener::bind(addr);
+    match (&CONFIG.global.tls_cert, &CONFIG.global.tls_key) {
+        (Some(cert), Some(key)) => Ok(listener.rustls(config_stream(cert, key)?).boxed()),
+        _ => Ok(listener.boxed()),
+    }
+}
+
+pub fn hsts() -> SetHeader {
+    SetHeader::new().overriding(
+        STRICT_TRANSPORT_SECURITY,
+        format!("max-age={}", CONFIG.global.hsts_max_age),
+    )
+}
+
+//去掉 Host 中的端口，兼容 [::1]:80 形式的 IPv6 地址
+fn hostname(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+#[handler]
+pub fn redirect(req: &Request) -> Response {
+    let host = match req.headers().get(HOST).and_then(|v| v.to_str().ok()) {
+        Some(host) => hostname(host),
+        None => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let port = CONFIG
+        .global
+        .listen_addr
+        .rsplit_once(':')
+        .map(|(_, port)| port)
+        .filter(|port| *port != "443")
+        .map(|port| format!(":{port}"))
+        .unwrap_or_default();
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    Redirect::permanent(format!("https://{host}{port}{path}")).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poem::http::header::LOCATION;
+    use poem::Endpoint;
+
+    #[test]
+    fn strip_port() {
+        let cases = [
+            ("example.com", "example.com"),
+            ("example.com:8080", "example.com"),
+            ("127.0.0.1:80", "127.0.0.1"),
+            ("[::1]:80", "[::1]"),
+            ("[::1]", "[::1]"),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(hostname(host), expected, "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn redirect_to_https() {
+        let port = CONFIG
+            .global
+            .listen_addr
+            .rsplit_once(':')
+            .map(|(_, port)| port)
+            .unwrap();
+        let req = Request::builder()
+            .uri("/prod-api/books-manager/book/list?page=2".parse().unwrap())
+            .header(HOST, "library.example.com:80")
+            .finish();
+        let resp = redirect.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            resp.headers().get(LOCATION).unwrap(),
+            &format!("https://library.example.com:{port}/prod-api/books-manager/book/list?page=2")
+        );
+
+        let resp = redirect.call(Request::builder().finish()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn reject_invalid_pem() {
+        let dir = std::env::temp_dir().join(format!("library-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert, "not a certificate").unwrap();
+        fs::write(&key, "not a key").unwrap();
+
+        let err = read(&dir.join("missing.pem"), &key).err().unwrap();
+        assert!(err.to_string().contains("missing.pem"));
+        let err = read(&cert, &key).err().unwrap();
+        assert!(err.to_string().contains("no certificate found"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
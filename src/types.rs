@@ -1,30 +1,39 @@
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::fmt;
 use std::str::FromStr;
 use validator::{
     validate_email, validate_length, validate_range, Validate, ValidationError, ValidationErrors,
 };
 
 use crate::error::Error;
+use crate::i18n;
 
-fn new_err(field: &'static str, s: &'static str) -> ValidationErrors {
+//code 为 locales 中 [validation] 段的键，见 i18n::validation_errors
+fn new_err(field: &'static str, code: &'static str) -> ValidationErrors {
     let mut v = ValidationErrors::new();
-    v.add(field, ValidationError::new(s));
+    v.add(field, ValidationError::new(code));
+    v
+}
+
+//name 为 locales 中 [field] 段的键
+fn new_bound_err(field: &'static str, code: &'static str, min: u32, max: u32) -> ValidationErrors {
+    let mut e = ValidationError::new(code);
+    e.add_param("name".into(), &field);
+    e.add_param("min".into(), &min);
+    e.add_param("max".into(), &max);
+    let mut v = ValidationErrors::new();
+    v.add(field, e);
     v
 }
 
 macro_rules! impl_validate_str {
-    ($structname: ident,$print_name: expr,$min: expr,$max: expr) => {
+    ($structname: ident,$name: expr,$min: expr,$max: expr) => {
         impl Validate for $structname {
             fn validate(&self) -> Result<(), validator::ValidationErrors> {
                 validate_length(&self.0, Some($min), Some($max), None)
                     .then(|| ())
-                    .ok_or_else(|| {
-                        new_err(
-                            stringify!($structname),
-                            concat!($print_name, "为长度在 ", $min, "~", $max, " 的中英文字符"),
-                        )
-                    })
+                    .ok_or_else(|| new_bound_err($name, "length", $min, $max))
             }
         }
         impl From<&str> for $structname {
@@ -32,29 +41,34 @@ macro_rules! impl_validate_str {
                 $structname(s.to_string())
             }
         }
+        impl $structname {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
     };
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Username(String);
-impl_validate_str!(Username, "用户名称", 1, 10);
+impl_validate_str!(Username, "username", 1, 10);
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Bookname(String);
-impl_validate_str!(Bookname, "书籍名称", 1, 50);
+impl_validate_str!(Bookname, "bookname", 1, 50);
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Introduction(String);
-impl_validate_str!(Introduction, "自我介绍", 0, 200);
+impl_validate_str!(Introduction, "introduction", 0, 200);
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Email(String);
 
 impl Validate for Email {
     fn validate(&self) -> Result<(), validator::ValidationErrors> {
         validate_email(&self.0)
             .then(|| ())
-            .ok_or_else(|| new_err("email", "邮箱格式不正确"))
+            .ok_or_else(|| new_err("email", "email"))
     }
 }
 
@@ -64,12 +78,18 @@ impl From<&str> for Email {
     }
 }
 
+impl Email {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Sid(String);
 
 impl Validate for Sid {
     fn validate(&self) -> Result<(), validator::ValidationErrors> {
-        let e = new_err("studentid", "学号为 12 位纯数字");
+        let e = new_err("studentid", "sid");
         if self.0.len() != 12 {
             return Err(e);
         }
@@ -90,33 +110,290 @@ impl From<&str> for Sid {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+//统一以 ISBN-13 纯数字形式存储，反序列化时会去除连字符和空格并将 ISBN-10 转换为 ISBN-13，
+//无法识别的输入原样保留，由 validate 报告错误
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct Isbn(String);
 
+impl<'de> Deserialize<'de> for Isbn {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.parse().unwrap_or(Isbn(s)))
+    }
+}
+
 impl Validate for Isbn {
     fn validate(&self) -> Result<(), validator::ValidationErrors> {
-        let e = new_err("isbn", "ISBN 号为 13 位纯数字");
-        if self.0.len() != 13 {
-            return Err(e);
-        }
+        self.0
+            .parse::<Isbn>()
+            .map(|_| ())
+            .map_err(|_| new_err("isbn", "isbn"))
+    }
+}
 
-        for c in self.0.chars() {
-            if !c.is_ascii_digit() {
-                return Err(e);
+impl FromStr for Isbn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s: String = s.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+        let b = s.as_bytes();
+
+        match b.len() {
+            10 => {
+                if !b[..9].iter().all(u8::is_ascii_digit) {
+                    return Err(Error::InvalidIsbn);
+                }
+                let check = match b[9] {
+                    b'X' | b'x' => 10,
+                    c if c.is_ascii_digit() => (c - b'0') as u32,
+                    _ => return Err(Error::InvalidIsbn),
+                };
+                let sum: u32 = b[..9]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (10 - i as u32) * (c - b'0') as u32)
+                    .sum::<u32>()
+                    + check;
+                if !sum.is_multiple_of(11) {
+                    return Err(Error::InvalidIsbn);
+                }
+
+                let body = format!("978{}", &s[..9]);
+                let check = ean13_check_digit(body.as_bytes());
+                Ok(Isbn(format!("{body}{check}")))
             }
+            13 => {
+                if !b.iter().all(u8::is_ascii_digit)
+                    || !(s.starts_with("978") || s.starts_with("979"))
+                    || ean13_check_digit(&b[..12]) != (b[12] - b'0') as u32
+                {
+                    return Err(Error::InvalidIsbn);
+                }
+                Ok(Isbn(s))
+            }
+            _ => Err(Error::InvalidIsbn),
         }
+    }
+}
 
-        Ok(())
+fn ean13_check_digit(digits: &[u8]) -> u32 {
+    let sum: u32 = digits
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let weight = if i % 2 == 0 { 1 } else { 3 };
+            weight * (c - b'0') as u32
+        })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+//出版者号区间（取组号后 7 位比较）及对应的出版者号长度
+type RegistrantRanges = &'static [(u32, u32, usize)];
+
+const GROUP_0_RANGES: RegistrantRanges = &[
+    (0, 1999999, 2),
+    (2000000, 6999999, 3),
+    (7000000, 8499999, 4),
+    (8500000, 8999999, 5),
+    (9000000, 9499999, 6),
+    (9500000, 9999999, 7),
+];
+
+const GROUP_1_RANGES: RegistrantRanges = &[
+    (0, 999999, 2),
+    (1000000, 3999999, 3),
+    (4000000, 5499999, 4),
+    (5500000, 8697999, 5),
+    (8698000, 9989999, 6),
+    (9990000, 9999999, 7),
+];
+
+const GROUP_7_RANGES: RegistrantRanges = &[
+    (0, 999999, 2),
+    (1000000, 4999999, 3),
+    (5000000, 7999999, 4),
+    (8000000, 8999999, 5),
+    (9000000, 9999999, 6),
+];
+
+//978 前缀下常用注册组，未收录的注册组不做分段
+const ISBN_GROUPS: &[(u8, RegistrantRanges)] = &[
+    (b'0', GROUP_0_RANGES),
+    (b'1', GROUP_1_RANGES),
+    (b'4', GROUP_0_RANGES),
+    (b'7', GROUP_7_RANGES),
+];
+
+impl Isbn {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    //带连字符的显示形式，如 978-7-5366-9293-0
+    pub fn hyphenated(&self) -> String {
+        let s = &self.0;
+        if s.len() != 13 || !s.starts_with("978") || !s.bytes().all(|c| c.is_ascii_digit()) {
+            return s.clone();
+        }
+
+        let group = s.as_bytes()[3];
+        let ranges = match ISBN_GROUPS.iter().find(|(g, _)| *g == group) {
+            Some((_, ranges)) => ranges,
+            None => return s.clone(),
+        };
+        let n: u32 = s[4..11].parse().unwrap_or_default();
+        match ranges
+            .iter()
+            .find(|(min, max, _)| (*min..=*max).contains(&n))
+        {
+            Some((_, _, len)) => format!(
+                "{}-{}-{}-{}-{}",
+                &s[..3],
+                &s[3..4],
+                &s[4..4 + len],
+                &s[4 + len..12],
+                &s[12..]
+            ),
+            None => s.clone(),
+        }
     }
 }
 
+//用于 API 响应：#[serde(serialize_with = "crate::types::isbn_hyphenated")]
+pub fn isbn_hyphenated<S>(isbn: &Isbn, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&isbn.hyphenated())
+}
+
+//数据库中的 TINYINT 读出为整数：#[serde(deserialize_with = "crate::types::bool_from_int")]
+pub fn bool_from_int<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrBool {
+        Int(i64),
+        Bool(bool),
+    }
+
+    Ok(match IntOrBool::deserialize(deserializer)? {
+        IntOrBool::Int(n) => n != 0,
+        IntOrBool::Bool(b) => b,
+    })
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Author(String);
-impl_validate_str!(Author, "作者名", 1, 20);
+impl_validate_str!(Author, "author", 1, 100);
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Press(String);
-impl_validate_str!(Press, "出版社", 1, 20);
+impl_validate_str!(Press, "press", 1, 100);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Edition(String);
+impl_validate_str!(Edition, "edition", 1, 50);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Language(String);
+impl_validate_str!(Language, "language", 1, 20);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Description(String);
+impl_validate_str!(Description, "description", 0, 2000);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CallNumber(String);
+impl_validate_str!(CallNumber, "call_number", 1, 50);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ShelfLocation(String);
+impl_validate_str!(ShelfLocation, "shelf_location", 1, 50);
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct Subject(String);
+impl_validate_str!(Subject, "subject", 1, 50);
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WithdrawnReason(String);
+impl_validate_str!(WithdrawnReason, "withdrawn_reason", 0, 200);
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct PublicationYear(u32);
+
+impl Validate for PublicationYear {
+    fn validate(&self) -> Result<(), validator::ValidationErrors> {
+        validate_range(&self.0, Some(&1000u32), Some(&2100u32))
+            .then_some(())
+            .ok_or_else(|| new_bound_err("publication_year", "range", 1000, 2100))
+    }
+}
+
+impl From<u32> for PublicationYear {
+    fn from(n: u32) -> Self {
+        PublicationYear(n)
+    }
+}
+
+impl PublicationYear {
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct PageCount(u32);
+
+impl Validate for PageCount {
+    fn validate(&self) -> Result<(), validator::ValidationErrors> {
+        validate_range(&self.0, Some(&1u32), Some(&100000u32))
+            .then_some(())
+            .ok_or_else(|| new_bound_err("page_count", "range", 1, 100000))
+    }
+}
+
+impl From<u32> for PageCount {
+    fn from(n: u32) -> Self {
+        PageCount(n)
+    }
+}
+
+impl PageCount {
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContributorRole {
+    Author,
+    Translator,
+    Editor,
+}
+
+impl ContributorRole {
+    pub const ALL: [ContributorRole; 3] = [
+        ContributorRole::Author,
+        ContributorRole::Translator,
+        ContributorRole::Editor,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContributorRole::Author => "author",
+            ContributorRole::Translator => "translator",
+            ContributorRole::Editor => "editor",
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 pub struct Stock(u32);
@@ -125,7 +402,7 @@ impl Validate for Stock {
     fn validate(&self) -> Result<(), validator::ValidationErrors> {
         validate_range(&self.0, Some(&0u32), Some(&100u32))
             .then(|| ())
-            .ok_or_else(|| new_err("stock", "书籍库存为 0~100 之间整数"))
+            .ok_or_else(|| new_bound_err("stock", "range", 0, 100))
     }
 }
 
@@ -150,12 +427,10 @@ pub struct Password(String);
 
 impl Validate for Password {
     fn validate(&self) -> Result<(), validator::ValidationErrors> {
-        RE_PASSWORD.is_match(&self.0).then(|| ()).ok_or_else(|| {
-            new_err(
-                "password",
-                "密码为 8~16 位大小写字母加数字的组合（不包含特殊字符）",
-            )
-        })
+        RE_PASSWORD
+            .is_match(&self.0)
+            .then(|| ())
+            .ok_or_else(|| new_err("password", "password"))
     }
 }
 
@@ -163,6 +438,30 @@ impl Password {
     pub fn encode(&self) -> Password {
         Password(format!("{:x}", md5::compute(self.0.as_bytes())))
     }
+
+    //生成 12 位同时包含大小写字母和数字的随机密码
+    pub fn generate() -> Password {
+        use rand::distributions::Alphanumeric;
+        use rand::Rng;
+
+        loop {
+            let s: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(12)
+                .map(char::from)
+                .collect();
+            if s.chars().any(|c| c.is_ascii_uppercase())
+                && s.chars().any(|c| c.is_ascii_lowercase())
+                && s.chars().any(|c| c.is_ascii_digit())
+            {
+                return Password(s);
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl From<&str> for Password {
@@ -179,10 +478,10 @@ impl Validate for Age {
         let age = self
             .0
             .parse()
-            .map_err(|_| new_err("age", "falied to parse int"))?;
+            .map_err(|_| new_bound_err("age", "range", 0, 100))?;
         validate_range(&age, Some(&0u32), Some(&100u32))
             .then(|| ())
-            .ok_or_else(|| new_err("age", "年龄为 0~100 间的整数"))
+            .ok_or_else(|| new_bound_err("age", "range", 0, 100))
     }
 }
 
@@ -201,7 +500,7 @@ impl Validate for Sex {
             "male" => Ok(()),
             "female" => Ok(()),
             "unknown" => Ok(()),
-            _ => Err(new_err("sex", "expected `male` `female` `unknown`")),
+            _ => Err(new_err("sex", "sex")),
         }
     }
 }
@@ -217,6 +516,7 @@ impl From<&str> for Sex {
 pub enum Role {
     Admin = 0,
     User = 1,
+    Librarian = 2,
 }
 
 #[derive(Deserialize_repr, Serialize_repr, Debug, Clone, PartialEq, Eq)]
@@ -226,12 +526,22 @@ pub enum Status {
     Enabled = 1,
 }
 
-impl ToString for Role {
-    fn to_string(&self) -> String {
-        match self {
-            Role::Admin => "admin".to_string(),
-            Role::User => "user".to_string(),
-        }
+//下架的书籍保留借阅记录，不出现在书籍列表和搜索结果中
+#[derive(Deserialize_repr, Serialize_repr, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum BookStatus {
+    #[default]
+    Available = 0,
+    Withdrawn = 1,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+            Role::Librarian => "librarian",
+        })
     }
 }
 
@@ -244,6 +554,19 @@ impl ToString for Status {
     }
 }
 
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "user" => Ok(Role::User),
+            "librarian" => Ok(Role::Librarian),
+            _ => Err(Error::InvalidData(i18n::message("detail.unknown_role"))),
+        }
+    }
+}
+
 impl FromStr for Status {
     type Err = Error;
 
@@ -251,9 +574,100 @@ impl FromStr for Status {
         match s {
             "enabled" => Ok(Status::Enabled),
             "disabled" => Ok(Status::Disabled),
-            _ => Err(Error::InvalidData(
-                "unknown status, excepted `enabled` or `disabled`".into(),
-            )),
+            _ => Err(Error::InvalidData(i18n::message("detail.unknown_status"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_isbn() {
+        let cases = [
+            ("9787536692930", Some("9787536692930")),
+            ("978-7-5366-9293-0", Some("9787536692930")),
+            ("978 7 5366 9293 0", Some("9787536692930")),
+            ("9791098765438", Some("9791098765438")),
+            ("7536692935", Some("9787536692930")),
+            ("7-5366-9293-5", Some("9787536692930")),
+            ("080442957X", Some("9780804429573")),
+            ("080442957x", Some("9780804429573")),
+            ("9787536692931", None),
+            ("7536692936", None),
+            ("0804429571", None),
+            ("X804429571", None),
+            ("9771234567003", None),
+            ("978753669293", None),
+            ("97875366929300", None),
+            ("978753669293a", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            let parsed = input.parse::<Isbn>().ok();
+            assert_eq!(parsed.as_ref().map(Isbn::as_str), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn hyphenate_isbn() {
+        let cases = [
+            ("9780198534532", "978-0-19-853453-2"),
+            ("9780306406157", "978-0-306-40615-7"),
+            ("9780743273565", "978-0-7432-7356-5"),
+            ("9780851310411", "978-0-85131-041-1"),
+            ("9780901690548", "978-0-901690-54-8"),
+            ("9780999999912", "978-0-9999999-1-2"),
+            ("9781402894626", "978-1-4028-9462-6"),
+            ("9781566199094", "978-1-56619-909-4"),
+            ("9781869800017", "978-1-869800-01-7"),
+            ("9784101092058", "978-4-10-109205-8"),
+            ("9787040123456", "978-7-04-012345-6"),
+            ("9787115123459", "978-7-115-12345-9"),
+            ("9787536692930", "978-7-5366-9293-0"),
+            ("9787802031005", "978-7-80203-100-5"),
+            ("9787900000019", "978-7-900000-01-9"),
+            //未收录的注册组和 979 前缀不分段
+            ("9783161484100", "9783161484100"),
+            ("9791098765438", "9791098765438"),
+        ];
+        for (input, expected) in cases {
+            let isbn: Isbn = input.parse().unwrap();
+            assert_eq!(isbn.hyphenated(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn deserialize_stored_isbn() {
+        //校验位错误的旧数据原样读出，由 validate 报告错误
+        let isbn: Isbn = serde_json::from_str("\"9787536692931\"").unwrap();
+        assert_eq!(isbn.as_str(), "9787536692931");
+        assert!(isbn.validate().is_err());
+
+        let isbn: Isbn = serde_json::from_str("\"7-5366-9293-5\"").unwrap();
+        assert_eq!(isbn.as_str(), "9787536692930");
+        assert!(isbn.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_isbn_error() {
+        #[derive(Validate)]
+        struct Req {
+            #[validate]
+            isbns: Vec<Isbn>,
         }
+
+        let req = Req {
+            isbns: vec![
+                "9787536692930".parse().unwrap(),
+                Isbn("9787536692931".into()),
+            ],
+        };
+        let err = Error::from(req.validate().unwrap_err());
+        assert!(matches!(err, Error::InvalidIsbn));
+
+        let err = Error::from(Sid("a".into()).validate().unwrap_err());
+        assert!(matches!(err, Error::InvalidData(_)));
     }
 }
@@ -0,0 +1,148 @@
+use crate::config::current;
+use crate::error::Error;
+use crate::CONFIG;
+use image::error::ImageError;
+use image::io::{Limits, Reader as ImageReader};
+use image::{DynamicImage, ImageFormat};
+use log::{debug, warn};
+use poem::web::Multipart;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+pub const UPLOAD_ROUTE: &str = "/uploads";
+const THUMBNAIL_DIR: &str = "thumb";
+const FILE_FIELD: &str = "file";
+//解码时的尺寸和内存上限，防止体积很小但像素极多的图片耗尽内存
+const MAX_IMAGE_SIDE: u32 = 8192;
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+pub fn image_url(name: &str) -> String {
+    format!("{UPLOAD_ROUTE}/{name}")
+}
+
+pub fn thumbnail_url(name: &str) -> String {
+    format!("{UPLOAD_ROUTE}/{THUMBNAIL_DIR}/{}", thumbnail_name(name))
+}
+
+//JPEG 原图的缩略图仍为 JPEG，其它格式统一为 PNG 以保留透明度
+fn thumbnail_name(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((hash, "jpg")) => format!("{hash}.jpg"),
+        Some((hash, _)) => format!("{hash}.png"),
+        None => format!("{name}.png"),
+    }
+}
+
+//读取 multipart 中名为 file 的字段，超过 upload.max_size 时报错
+pub async fn read_image(mut multipart: Multipart) -> Result<Vec<u8>, Error> {
+    let max_size = current().upload.max_size;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        debug!("{e}");
+        Error::InvalidImage
+    })? {
+        if field.name() != Some(FILE_FIELD) {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        field
+            .into_async_read()
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| {
+                debug!("{e}");
+                Error::InvalidImage
+            })?;
+        if data.len() > max_size {
+            return Err(Error::ImageTooLarge);
+        }
+        return Ok(data);
+    }
+
+    Err(Error::InvalidImage)
+}
+
+//以内容的 SHA-256 命名保存原图并生成缩略图，返回文件名
+pub async fn save_image(data: Vec<u8>) -> Result<String, Error> {
+    tokio::task::spawn_blocking(move || save_image_blocking(&data))
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            Error::FailedToSaveImage
+        })?
+}
+
+fn save_image_blocking(data: &[u8]) -> Result<String, Error> {
+    let format = image::guess_format(data).map_err(|_| Error::InvalidImage)?;
+    let ext = match format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Png => "png",
+        ImageFormat::Gif => "gif",
+        ImageFormat::WebP => "webp",
+        _ => return Err(Error::InvalidImage),
+    };
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let img = reader.decode().map_err(|e| {
+        debug!("{e}");
+        match e {
+            ImageError::Limits(_) => Error::ImageTooLarge,
+            _ => Error::InvalidImage,
+        }
+    })?;
+
+    let name = format!("{:x}.{ext}", Sha256::digest(data));
+    let dir = Path::new(&CONFIG.upload.dir);
+    let thumb_dir = dir.join(THUMBNAIL_DIR);
+    let save_err = |e: &dyn std::fmt::Display| {
+        debug!("{e}");
+        Error::FailedToSaveImage
+    };
+    fs::create_dir_all(&thumb_dir).map_err(|e| save_err(&e))?;
+
+    let path = dir.join(&name);
+    if !path.exists() {
+        fs::write(&path, data).map_err(|e| save_err(&e))?;
+    }
+
+    let thumb_name = thumbnail_name(&name);
+    let thumb_path = thumb_dir.join(&thumb_name);
+    if !thumb_path.exists() {
+        let size = current().upload.thumbnail_size;
+        let thumb = img.thumbnail(size, size);
+        let (thumb, thumb_format) = if thumb_name.ends_with(".jpg") {
+            (DynamicImage::ImageRgb8(thumb.to_rgb8()), ImageFormat::Jpeg)
+        } else {
+            (thumb, ImageFormat::Png)
+        };
+        thumb
+            .save_with_format(&thumb_path, thumb_format)
+            .map_err(|e| save_err(&e))?;
+    }
+
+    Ok(name)
+}
+
+//删除原图和缩略图，文件不存在时忽略
+pub async fn remove_image(name: &str) {
+    let dir = Path::new(&CONFIG.upload.dir);
+    for path in [
+        dir.join(name),
+        dir.join(THUMBNAIL_DIR).join(thumbnail_name(name)),
+    ] {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => debug!("removed {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to remove {}: {e}", path.display()),
+        }
+    }
+}